@@ -0,0 +1,360 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::Path;
+
+use data_encoding::HEXUPPER;
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use sled::Db;
+use thiserror::Error;
+
+use crate::dispatcher::{MessageBackend, Severity};
+
+/// Tree of the hash database holding the parsed account files of the baseline.
+static ACCOUNTS_TREE: &str = "accounts";
+
+pub static PASSWD: &str = "/etc/passwd";
+pub static SHADOW: &str = "/etc/shadow";
+pub static GROUP: &str = "/etc/group";
+
+#[derive(Debug, Error)]
+pub enum AccountsError {
+    #[error(transparent)]
+    Sled(#[from] sled::Error),
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub uid: u32,
+    pub gid: u32,
+    pub home: String,
+    pub shell: String,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Group {
+    pub gid: u32,
+    pub members: BTreeSet<String>,
+}
+
+/// Parsed contents of an account file. Password hashes of `/etc/shadow` are only kept as digest
+/// so that they do not end up in the database.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum Snapshot {
+    Passwd(BTreeMap<String, User>),
+    Group(BTreeMap<String, Group>),
+    Shadow(BTreeMap<String, String>),
+}
+
+/// Returns `true` if `path` is one of the files holding user accounts and groups.
+pub fn is_account_file(path: &Path) -> bool {
+    [PASSWD, SHADOW, GROUP]
+        .iter()
+        .any(|file| path == Path::new(file))
+}
+
+pub fn parse_passwd(contents: &str) -> BTreeMap<String, User> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            if fields.len() < 7 {
+                return None;
+            }
+            let user = User {
+                uid: fields[2].parse().ok()?,
+                gid: fields[3].parse().ok()?,
+                home: fields[5].to_string(),
+                shell: fields[6].to_string(),
+            };
+            Some((fields[0].to_string(), user))
+        })
+        .collect()
+}
+
+fn parse_group(contents: &str) -> BTreeMap<String, Group> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            if fields.len() < 4 {
+                return None;
+            }
+            let group = Group {
+                gid: fields[2].parse().ok()?,
+                members: fields[3]
+                    .split(',')
+                    .filter(|member| !member.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+            };
+            Some((fields[0].to_string(), group))
+        })
+        .collect()
+}
+
+/// Maps user names to the digest of their password field. An empty password maps to an empty
+/// string.
+fn parse_shadow(contents: &str) -> BTreeMap<String, String> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?;
+            let password = fields.next()?;
+            let password_digest = match password.is_empty() {
+                true => String::new(),
+                false => HEXUPPER.encode(digest(&SHA256, password.as_bytes()).as_ref()),
+            };
+            Some((name.to_string(), password_digest))
+        })
+        .collect()
+}
+
+fn parse(path: &Path, contents: &str) -> Option<Snapshot> {
+    match path.to_str()? {
+        file if file == PASSWD => Some(Snapshot::Passwd(parse_passwd(contents))),
+        file if file == GROUP => Some(Snapshot::Group(parse_group(contents))),
+        file if file == SHADOW => Some(Snapshot::Shadow(parse_shadow(contents))),
+        _ => None,
+    }
+}
+
+/// A change of a user account or group.
+#[derive(Debug, PartialEq)]
+pub enum AccountChange {
+    UserAdded { name: String, user: User },
+    UserRemoved { name: String },
+    UserModified { name: String, old: User, new: User },
+    GroupAdded { name: String, group: Group },
+    GroupRemoved { name: String },
+    GroupMemberAdded { name: String, member: String },
+    GroupMemberRemoved { name: String, member: String },
+    PasswordChanged { name: String },
+    PasswordCleared { name: String },
+}
+
+impl AccountChange {
+    pub fn severity(&self) -> Severity {
+        match self {
+            AccountChange::UserAdded { user, .. } if user.uid == 0 => Severity::Critical,
+            AccountChange::UserModified { new, .. } if new.uid == 0 => Severity::Critical,
+            AccountChange::PasswordCleared { .. } => Severity::Critical,
+            AccountChange::GroupMemberAdded { .. } => Severity::High,
+            AccountChange::UserAdded { .. } => Severity::High,
+            _ => Severity::Warning,
+        }
+    }
+}
+
+impl fmt::Display for AccountChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AccountChange::UserAdded { name, user } => write!(
+                f,
+                "User <b>{name}</b> added with UID {} (home: <code>{}</code>, shell: <code>{}</code>)",
+                user.uid, user.home, user.shell
+            ),
+            AccountChange::UserRemoved { name } => write!(f, "User <b>{name}</b> removed"),
+            AccountChange::UserModified { name, old, new } => {
+                let mut changes = vec![];
+                if old.uid != new.uid {
+                    changes.push(format!("UID {} → {}", old.uid, new.uid));
+                }
+                if old.gid != new.gid {
+                    changes.push(format!("GID {} → {}", old.gid, new.gid));
+                }
+                if old.home != new.home {
+                    changes.push(format!("home {} → {}", old.home, new.home));
+                }
+                if old.shell != new.shell {
+                    changes.push(format!("shell {} → {}", old.shell, new.shell));
+                }
+                write!(f, "User <b>{name}</b> changed: {}", changes.join(", "))
+            }
+            AccountChange::GroupAdded { name, group } => {
+                write!(f, "Group <b>{name}</b> added with GID {}", group.gid)
+            }
+            AccountChange::GroupRemoved { name } => write!(f, "Group <b>{name}</b> removed"),
+            AccountChange::GroupMemberAdded { name, member } => {
+                write!(f, "User <b>{member}</b> added to group <b>{name}</b>")
+            }
+            AccountChange::GroupMemberRemoved { name, member } => {
+                write!(f, "User <b>{member}</b> removed from group <b>{name}</b>")
+            }
+            AccountChange::PasswordChanged { name } => {
+                write!(f, "Password of user <b>{name}</b> changed")
+            }
+            AccountChange::PasswordCleared { name } => {
+                write!(f, "User <b>{name}</b> has an empty password")
+            }
+        }
+    }
+}
+
+impl From<&AccountChange> for MessageBackend {
+    fn from(value: &AccountChange) -> Self {
+        MessageBackend::new_now("Account change".to_string(), value.to_string())
+            .with_severity(value.severity())
+    }
+}
+
+fn diff_maps<V: PartialEq>(
+    old: &BTreeMap<String, V>,
+    new: &BTreeMap<String, V>,
+) -> (Vec<String>, Vec<String>, Vec<String>) {
+    let added = new
+        .keys()
+        .filter(|k| !old.contains_key(*k))
+        .cloned()
+        .collect();
+    let removed = old
+        .keys()
+        .filter(|k| !new.contains_key(*k))
+        .cloned()
+        .collect();
+    let modified = new
+        .iter()
+        .filter(|(k, v)| old.get(*k).is_some_and(|old| old != *v))
+        .map(|(k, _)| k.clone())
+        .collect();
+    (added, removed, modified)
+}
+
+fn diff(old: &Snapshot, new: &Snapshot) -> Vec<AccountChange> {
+    let mut changes = vec![];
+    match (old, new) {
+        (Snapshot::Passwd(old), Snapshot::Passwd(new)) => {
+            let (added, removed, modified) = diff_maps(old, new);
+            for name in added {
+                let user = new[&name].clone();
+                changes.push(AccountChange::UserAdded { name, user });
+            }
+            for name in removed {
+                changes.push(AccountChange::UserRemoved { name });
+            }
+            for name in modified {
+                let (old, new) = (old[&name].clone(), new[&name].clone());
+                changes.push(AccountChange::UserModified { name, old, new });
+            }
+        }
+        (Snapshot::Group(old), Snapshot::Group(new)) => {
+            let (added, removed, modified) = diff_maps(old, new);
+            for name in added {
+                let group = new[&name].clone();
+                changes.push(AccountChange::GroupAdded { name, group });
+            }
+            for name in removed {
+                changes.push(AccountChange::GroupRemoved { name });
+            }
+            for name in modified {
+                let (old, new) = (&old[&name], &new[&name]);
+                for member in new.members.difference(&old.members) {
+                    changes.push(AccountChange::GroupMemberAdded {
+                        name: name.clone(),
+                        member: member.clone(),
+                    });
+                }
+                for member in old.members.difference(&new.members) {
+                    changes.push(AccountChange::GroupMemberRemoved {
+                        name: name.clone(),
+                        member: member.clone(),
+                    });
+                }
+            }
+        }
+        (Snapshot::Shadow(old), Snapshot::Shadow(new)) => {
+            // added and removed users are reported through /etc/passwd
+            let (_, _, modified) = diff_maps(old, new);
+            for name in modified {
+                match new[&name].is_empty() {
+                    true => changes.push(AccountChange::PasswordCleared { name }),
+                    false => changes.push(AccountChange::PasswordChanged { name }),
+                }
+            }
+        }
+        _ => {}
+    }
+    changes
+}
+
+/// Store the parsed account file at `path` in the hash database `db`.
+pub fn record_snapshot(db: &Db, path: &Path) -> Result<(), AccountsError> {
+    let contents = std::fs::read_to_string(path)?;
+    if let Some(snapshot) = parse(path, &contents) {
+        db.open_tree(ACCOUNTS_TREE)?.insert(
+            path.to_string_lossy().as_bytes(),
+            serde_json::to_vec(&snapshot)?,
+        )?;
+    }
+    Ok(())
+}
+
+/// Store snapshots of the account files located below `directories`.
+pub fn record_snapshots(db: &Db, directories: &[&Path]) -> Result<(), AccountsError> {
+    for path in [PASSWD, SHADOW, GROUP].map(Path::new) {
+        if path.exists() && directories.iter().any(|d| path.starts_with(d)) {
+            record_snapshot(db, path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Compare the account file at `path` with its snapshot in `db`. Returns `None` if there is no
+/// snapshot to compare with. Only `--init` replaces the snapshot.
+pub fn diff_snapshot(db: &Db, path: &Path) -> Result<Option<Vec<AccountChange>>, AccountsError> {
+    let tree = db.open_tree(ACCOUNTS_TREE)?;
+    let key = path.to_string_lossy();
+    let Some(old) = tree.get(key.as_bytes())? else {
+        return Ok(None);
+    };
+    let old: Snapshot = serde_json::from_slice(&old)?;
+    let contents = std::fs::read_to_string(path)?;
+    let Some(new) = parse(path, &contents) else {
+        return Ok(None);
+    };
+    Ok(Some(diff(&old, &new)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_passwd_and_shadow() {
+        let old = Snapshot::Passwd(parse_passwd(
+            "root:x:0:0:root:/root:/bin/bash\nuser1:x:1000:1000::/home/user1:/bin/bash\n",
+        ));
+        let new = Snapshot::Passwd(parse_passwd(
+            "root:x:0:0:root:/root:/bin/bash\nmallory:x:0:0::/home/mallory:/bin/sh\n",
+        ));
+        let changes = diff(&old, &new);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(
+            changes[0].to_string(),
+            "User <b>mallory</b> added with UID 0 (home: <code>/home/mallory</code>, shell: <code>/bin/sh</code>)"
+        );
+        assert_eq!(changes[0].severity(), Severity::Critical);
+        assert_eq!(
+            changes[1],
+            AccountChange::UserRemoved {
+                name: "user1".to_string()
+            }
+        );
+
+        let old = parse_shadow("root:$y$j9T$secret:19000:0:99999:7:::\n");
+        assert!(!old["root"].contains("secret"));
+        let new = parse_shadow("root::19000:0:99999:7:::\n");
+        let changes = diff(&Snapshot::Shadow(old), &Snapshot::Shadow(new));
+        assert_eq!(
+            changes,
+            vec![AccountChange::PasswordCleared {
+                name: "root".to_string()
+            }]
+        );
+    }
+}
@@ -0,0 +1,768 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use lazy_static::lazy_static;
+use regex::Regex;
+use thiserror::Error;
+use tokio::time;
+
+use crate::authentication_logs::{open_log, save_offset};
+use crate::config::Config;
+use crate::dispatcher::{escape_html, MessageBackend, Severity, SnitchDispatcher};
+use crate::persist::{open_watcher_database, PersistError};
+use crate::persistence::ChangeKind;
+
+const INTERVAL: u64 = 1000;
+
+/// Name of the database of the watcher holding the offset of the audit log.
+static WATCHER_NAME: &str = "audit";
+
+/// Seconds after which the records of an event without `EOE` are reported.
+const EVENT_TIMEOUT: i64 = 5;
+
+/// Separates the raw fields from the interpreted ones if auditd uses `log_format = ENRICHED`.
+const ENRICHED_SEPARATOR: char = '\x1d';
+
+/// Audit user id of processes not started from a login session.
+const UNSET_AUID: &str = "4294967295";
+
+static ARCH_X86_64: &str = "c000003e";
+
+/// Fields which hold strings. auditd hex encodes them if they contain spaces or special
+/// characters.
+static STRING_FIELDS: [&str; 6] = ["name", "exe", "comm", "cwd", "proctitle", "acct"];
+
+/// Syscalls which modify the paths they are called on. Opening a file only modifies it if it is
+/// opened for writing.
+static MODIFYING_SYSCALLS: [&str; 24] = [
+    "truncate",
+    "rename",
+    "renameat",
+    "renameat2",
+    "unlink",
+    "unlinkat",
+    "rmdir",
+    "mkdir",
+    "mkdirat",
+    "link",
+    "linkat",
+    "symlink",
+    "symlinkat",
+    "chmod",
+    "fchmodat",
+    "chown",
+    "lchown",
+    "fchownat",
+    "setxattr",
+    "lsetxattr",
+    "removexattr",
+    "lremovexattr",
+    "mknod",
+    "mknodat",
+];
+
+/// `O_WRONLY | O_RDWR | O_CREAT | O_TRUNC`
+const WRITE_FLAGS: u64 = 0o1 | 0o2 | 0o100 | 0o1000;
+
+lazy_static! {
+    static ref RECORD: Regex = Regex::new(
+        r"^type=(?P<type>\S+) msg=audit\((?P<seconds>\d+)\.(?P<millis>\d+):(?P<serial>\d+)\):\s*(?P<fields>.*)$"
+    )
+    .unwrap();
+    static ref FIELD: Regex =
+        Regex::new(r#"(?P<key>[\w-]+)=(?:"(?P<quoted>[^"]*)"|'(?P<nested>[^']*)'|(?P<plain>\S*))"#)
+            .unwrap();
+}
+
+#[derive(Debug, Error)]
+pub enum AuditError {
+    #[error("no audit log configured")]
+    NoLogFile,
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+    #[error(transparent)]
+    Persist(#[from] PersistError),
+}
+
+/// Name of x86_64 syscalls. On other architectures auditd needs `log_format = ENRICHED`.
+fn x86_64_syscall(number: &str) -> Option<&'static str> {
+    let name = match number {
+        "2" => "open",
+        "59" => "execve",
+        "76" => "truncate",
+        "82" => "rename",
+        "83" => "mkdir",
+        "84" => "rmdir",
+        "86" => "link",
+        "87" => "unlink",
+        "88" => "symlink",
+        "90" => "chmod",
+        "92" => "chown",
+        "94" => "lchown",
+        "133" => "mknod",
+        "188" => "setxattr",
+        "189" => "lsetxattr",
+        "197" => "removexattr",
+        "198" => "lremovexattr",
+        "257" => "openat",
+        "258" => "mkdirat",
+        "259" => "mknodat",
+        "260" => "fchownat",
+        "263" => "unlinkat",
+        "264" => "renameat",
+        "265" => "linkat",
+        "266" => "symlinkat",
+        "268" => "fchmodat",
+        "316" => "renameat2",
+        "322" => "execveat",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Decode a hex encoded string value. Null bytes, which separate the arguments in `proctitle`,
+/// are replaced by spaces.
+fn decode_hex(value: &str) -> Option<String> {
+    if value.is_empty() || !value.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes = (0..value.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&value[index..index + 2], 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    let decoded = String::from_utf8_lossy(&bytes).replace('\0', " ");
+    Some(decoded.trim_end().to_string())
+}
+
+/// A line of the audit log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditRecord {
+    pub kind: String,
+    pub timestamp: DateTime<Utc>,
+    /// Records of the same event share the serial number.
+    pub serial: u64,
+    /// Raw fields in lower case, interpreted fields of the enriched format in upper case. The
+    /// fields of a nested `msg='...'` are included.
+    pub fields: BTreeMap<String, String>,
+}
+
+/// Parse the fields of a record. The arguments `a0`, `a1`, ... are strings in `EXECVE` records
+/// and numbers in `SYSCALL` records.
+fn parse_fields(text: &str, arguments: bool, fields: &mut BTreeMap<String, String>) {
+    for cap in FIELD.captures_iter(text) {
+        let key = cap["key"].to_string();
+        if let Some(nested) = cap.name("nested") {
+            parse_fields(nested.as_str(), arguments, fields);
+            continue;
+        }
+        let value = match (cap.name("quoted"), cap.name("plain")) {
+            (Some(quoted), _) => quoted.as_str().to_string(),
+            (None, Some(plain)) => {
+                let is_string = STRING_FIELDS.contains(&key.as_str())
+                    || (arguments && key.starts_with('a') && key[1..].parse::<u32>().is_ok());
+                match is_string {
+                    true => decode_hex(plain.as_str()).unwrap_or(plain.as_str().to_string()),
+                    false => plain.as_str().to_string(),
+                }
+            }
+            (None, None) => String::new(),
+        };
+        fields.insert(key, value);
+    }
+}
+
+pub fn parse_record(line: &str) -> Option<AuditRecord> {
+    let cap = RECORD.captures(line.trim_end())?;
+    let seconds: i64 = cap["seconds"].parse().ok()?;
+    let millis: u32 = cap["millis"].parse().ok()?;
+    let mut fields = BTreeMap::new();
+    let (raw, enriched) = cap["fields"]
+        .split_once(ENRICHED_SEPARATOR)
+        .unwrap_or((&cap["fields"], ""));
+    let arguments = &cap["type"] == "EXECVE";
+    parse_fields(raw, arguments, &mut fields);
+    parse_fields(enriched, arguments, &mut fields);
+    Some(AuditRecord {
+        kind: cap["type"].to_string(),
+        timestamp: DateTime::from_timestamp(seconds, millis * 1_000_000)?,
+        serial: cap["serial"].parse().ok()?,
+        fields,
+    })
+}
+
+/// The records of one audit event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEvent {
+    pub records: Vec<AuditRecord>,
+}
+
+impl AuditEvent {
+    fn record(&self, kind: &str) -> Option<&AuditRecord> {
+        self.records.iter().find(|record| record.kind == kind)
+    }
+
+    fn timestamp(&self) -> DateTime<Utc> {
+        self.records[0].timestamp
+    }
+
+    fn syscall(&self) -> Option<&str> {
+        let syscall = self.record("SYSCALL")?;
+        if let Some(name) = syscall.fields.get("SYSCALL") {
+            return Some(name);
+        }
+        match syscall.fields.get("arch").map(String::as_str) {
+            Some(arch) if arch == ARCH_X86_64 => x86_64_syscall(syscall.fields.get("syscall")?),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if the syscall may have modified the paths of the event.
+    fn is_modifying(&self) -> bool {
+        let Some(syscall) = self.syscall() else {
+            return false;
+        };
+        let fields = &self.record("SYSCALL").unwrap().fields;
+        // the flags are the second argument of open and the third of openat
+        let flags = match syscall {
+            "open" => fields.get("a1"),
+            "openat" => fields.get("a2"),
+            _ => return MODIFYING_SYSCALLS.contains(&syscall),
+        };
+        flags
+            .and_then(|flags| u64::from_str_radix(flags, 16).ok())
+            .is_some_and(|flags| flags & WRITE_FLAGS != 0)
+    }
+}
+
+/// Groups records into events by their serial. Events of syscalls are terminated by an `EOE`
+/// record, the records of different events may interleave.
+#[derive(Debug, Default)]
+pub struct EventAssembler {
+    pending: BTreeMap<u64, AuditEvent>,
+}
+
+impl EventAssembler {
+    /// Add `record` and return the events it completes, including events without `EOE` that are
+    /// older than [`EVENT_TIMEOUT`] relative to `record`.
+    pub fn push(&mut self, record: AuditRecord) -> Vec<AuditEvent> {
+        let newest = record.timestamp;
+        let mut events = vec![];
+        if record.kind == "EOE" {
+            events.extend(self.pending.remove(&record.serial));
+        } else {
+            self.pending
+                .entry(record.serial)
+                .or_insert_with(|| AuditEvent { records: vec![] })
+                .records
+                .push(record);
+        }
+        let expired: Vec<u64> = self
+            .pending
+            .iter()
+            .filter(|(_, event)| event.timestamp() + TimeDelta::seconds(EVENT_TIMEOUT) < newest)
+            .map(|(serial, _)| *serial)
+            .collect();
+        events.extend(
+            expired
+                .iter()
+                .filter_map(|serial| self.pending.remove(serial)),
+        );
+        events.sort_by_key(|event| event.records[0].serial);
+        events
+    }
+
+    /// Return the pending events of single records like `USER_LOGIN`, which have no `EOE`.
+    pub fn flush(&mut self) -> Vec<AuditEvent> {
+        let serials: Vec<u64> = self
+            .pending
+            .iter()
+            .filter(|(_, event)| event.record("SYSCALL").is_none())
+            .map(|(serial, _)| *serial)
+            .collect();
+        serials
+            .iter()
+            .filter_map(|serial| self.pending.remove(serial))
+            .collect()
+    }
+
+    /// Group the records of the lines of `contents`.
+    pub fn process_contents(&mut self, contents: &str) -> Vec<AuditEvent> {
+        let mut events: Vec<AuditEvent> = contents
+            .lines()
+            .filter_map(parse_record)
+            .flat_map(|record| self.push(record))
+            .collect();
+        events.extend(self.flush());
+        events
+    }
+}
+
+/// The process an audit event is attributed to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Actor {
+    pub pid: Option<String>,
+    /// The user who logged in, even after `sudo` or `su`.
+    pub auid: Option<String>,
+    pub uid: Option<String>,
+    pub exe: Option<String>,
+    pub cmdline: Option<String>,
+}
+
+/// Prefer the user name of the enriched format over the numeric id.
+fn user(fields: &BTreeMap<String, String>, field: &str) -> Option<String> {
+    let id = fields.get(field)?;
+    if id == UNSET_AUID {
+        return Some("unset".to_string());
+    }
+    match fields.get(&field.to_uppercase()) {
+        Some(name) => Some(format!("{name} ({id})")),
+        None => Some(id.clone()),
+    }
+}
+
+impl Actor {
+    fn from_event(event: &AuditEvent) -> Actor {
+        let fields = &event.records[0].fields;
+        let cmdline = event
+            .record("PROCTITLE")
+            .and_then(|record| record.fields.get("proctitle"))
+            .or_else(|| event.record("SYSCALL")?.fields.get("comm"))
+            .cloned();
+        Actor {
+            pid: fields.get("pid").cloned(),
+            auid: user(fields, "auid"),
+            uid: user(fields, "uid"),
+            exe: fields.get("exe").cloned(),
+            cmdline,
+        }
+    }
+}
+
+impl fmt::Display for Actor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let unknown = "unknown".to_string();
+        write!(
+            f,
+            "pid: {}\nauid: {}\nuid: {}\nexe: {}\ncmdline: {}",
+            self.pid.as_ref().unwrap_or(&unknown),
+            escape_html(self.auid.as_ref().unwrap_or(&unknown)),
+            escape_html(self.uid.as_ref().unwrap_or(&unknown)),
+            escape_html(self.exe.as_ref().unwrap_or(&unknown)),
+            escape_html(self.cmdline.as_ref().unwrap_or(&unknown)),
+        )
+    }
+}
+
+/// What snitch reports about an audit event.
+#[derive(Debug, PartialEq)]
+pub enum AuditAlert {
+    /// A watched path was modified.
+    FileChange {
+        path: PathBuf,
+        kind: ChangeKind,
+        actor: Actor,
+        timestamp: DateTime<Utc>,
+    },
+    /// A program was executed by a syscall matching an audit rule with a key.
+    Command {
+        key: String,
+        arguments: String,
+        actor: Actor,
+        timestamp: DateTime<Utc>,
+    },
+    Login {
+        account: String,
+        address: Option<String>,
+        success: bool,
+        actor: Actor,
+        timestamp: DateTime<Utc>,
+    },
+}
+
+impl AuditAlert {
+    /// The alerts of `event` for the directories of `config`.
+    pub fn from_event(event: &AuditEvent, config: &Config) -> Vec<AuditAlert> {
+        let actor = Actor::from_event(event);
+        let timestamp = event.timestamp();
+        if let Some(login) = event.record("USER_LOGIN") {
+            let fields = &login.fields;
+            let account = fields
+                .get("acct")
+                .cloned()
+                .or_else(|| user(fields, "id"))
+                .unwrap_or("unknown".to_string());
+            return vec![AuditAlert::Login {
+                account,
+                address: fields.get("addr").filter(|addr| *addr != "?").cloned(),
+                success: fields.get("res").is_some_and(|res| res == "success"),
+                actor,
+                timestamp,
+            }];
+        }
+        let Some(syscall) = event.record("SYSCALL") else {
+            return vec![];
+        };
+        if syscall
+            .fields
+            .get("success")
+            .is_some_and(|success| success != "yes")
+        {
+            return vec![];
+        }
+        if let Some(execve) = event.record("EXECVE") {
+            let Some(key) = syscall.fields.get("key").filter(|key| *key != "(null)") else {
+                return vec![];
+            };
+            let argc: usize = execve
+                .fields
+                .get("argc")
+                .and_then(|argc| argc.parse().ok())
+                .unwrap_or(0);
+            let arguments = (0..argc)
+                .filter_map(|index| execve.fields.get(&format!("a{index}")))
+                .cloned()
+                .collect::<Vec<String>>()
+                .join(" ");
+            return vec![AuditAlert::Command {
+                key: key.clone(),
+                arguments,
+                actor,
+                timestamp,
+            }];
+        }
+
+        let cwd = event
+            .record("CWD")
+            .and_then(|record| record.fields.get("cwd"))
+            .map(PathBuf::from)
+            .unwrap_or_default();
+        // relative names of *at syscalls are relative to the directory of their PARENT item. The
+        // PARENT items either precede their items or, for renames, all of them come first, so
+        // the nth PARENT item belongs to the nth other item.
+        let (parents, items): (Vec<&AuditRecord>, Vec<&AuditRecord>) = event
+            .records
+            .iter()
+            .filter(|record| record.kind == "PATH")
+            .partition(|record| {
+                record
+                    .fields
+                    .get("nametype")
+                    .is_some_and(|kind| kind == "PARENT")
+            });
+        let parents: Vec<PathBuf> = parents
+            .iter()
+            .map(|record| cwd.join(record.fields.get("name").map_or("", String::as_str)))
+            .collect();
+        let modifying = event.is_modifying();
+        items
+            .iter()
+            .enumerate()
+            .filter_map(|(index, record)| {
+                let kind = match record.fields.get("nametype")?.as_str() {
+                    "CREATE" => ChangeKind::Created,
+                    "DELETE" => ChangeKind::Removed,
+                    "NORMAL" if modifying => ChangeKind::Modified,
+                    _ => return None,
+                };
+                let name = Path::new(record.fields.get("name")?);
+                let path = match parents.get(index) {
+                    Some(parent) if name.is_relative() => parent.join(name.file_name()?),
+                    _ => cwd.join(name),
+                };
+                is_watched(config, &path).then(|| AuditAlert::FileChange {
+                    path,
+                    kind,
+                    actor: actor.clone(),
+                    timestamp,
+                })
+            })
+            .collect()
+    }
+}
+
+fn is_watched(config: &Config, path: &Path) -> bool {
+    config
+        .directories()
+        .iter()
+        .any(|directory| path.starts_with(directory))
+}
+
+impl From<&AuditAlert> for MessageBackend {
+    fn from(value: &AuditAlert) -> Self {
+        let (mut message, timestamp) = match value {
+            AuditAlert::FileChange {
+                path,
+                kind,
+                actor,
+                timestamp,
+            } => {
+                let title = match kind {
+                    ChangeKind::Created => "File created",
+                    ChangeKind::Modified => "File modified",
+                    ChangeKind::Removed => "File removed",
+                };
+                let message = MessageBackend::new_now(
+                    title.to_string(),
+                    format!(
+                        "<code>{}</code>\n\n{actor}",
+                        escape_html(&path.to_string_lossy())
+                    ),
+                );
+                (message, timestamp)
+            }
+            AuditAlert::Command {
+                key,
+                arguments,
+                actor,
+                timestamp,
+            } => {
+                let message = MessageBackend::new_now(
+                    format!("Command executed ({key})"),
+                    format!("<code>{}</code>\n\n{actor}", escape_html(arguments)),
+                );
+                (message, timestamp)
+            }
+            AuditAlert::Login {
+                account,
+                address,
+                success,
+                actor,
+                timestamp,
+            } => {
+                let account = escape_html(account);
+                let title = match success {
+                    true => format!("Login of {account}"),
+                    false => format!("Failed login of {account}"),
+                };
+                let source = address
+                    .as_ref()
+                    .map(|address| format!(" from <code>{}</code>", escape_html(address)))
+                    .unwrap_or_default();
+                let severity = match success {
+                    true => Severity::Info,
+                    false => Severity::Warning,
+                };
+                let message =
+                    MessageBackend::new_now(title, format!("<b>{account}</b>{source}\n\n{actor}"))
+                        .with_severity(severity);
+                (message, timestamp)
+            }
+        };
+        message.timestamp = *timestamp;
+        message
+    }
+}
+
+/// Follow the audit log and dispatch alerts for modifications of the configured directories,
+/// executions matching keyed audit rules and logins.
+pub async fn watch_audit_log(
+    dispatcher: &SnitchDispatcher,
+    config: &Config,
+) -> Result<(), AuditError> {
+    let Some(filename) = config.audit_log.as_deref() else {
+        return Err(AuditError::NoLogFile);
+    };
+    info!("start watching {:?}", filename);
+    let state = open_watcher_database(config, WATCHER_NAME)?;
+    let mut tail = open_log(&state, config, filename).await?;
+    let mut assembler = EventAssembler::default();
+    let mut interval = time::interval(Duration::from_millis(INTERVAL));
+
+    loop {
+        interval.tick().await;
+        let contents = match tail.read_lines().await {
+            Ok(contents) => contents,
+            Err(err) => {
+                warn!("failed reading {:?}: {err}", filename);
+                continue;
+            }
+        };
+        if contents.is_empty() {
+            continue;
+        }
+        for event in assembler.process_contents(&contents) {
+            for alert in AuditAlert::from_event(&event, config).iter() {
+                let _ = dispatcher
+                    .dispatch(alert.into())
+                    .await
+                    .inspect_err(|e| error!("failed to dispatch message: {:?}", e));
+            }
+        }
+        let _ = save_offset(&state, filename, tail.offset())
+            .inspect_err(|e| warn!("failed saving log offset: {e}"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alerts() -> Vec<AuditAlert> {
+        let mut config = Config::demo_config();
+        config.directories = vec!["/etc".to_string()];
+        let contents = std::fs::read_to_string("test/audit.log").unwrap();
+        EventAssembler::default()
+            .process_contents(&contents)
+            .iter()
+            .flat_map(|event| AuditAlert::from_event(event, &config))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_record() {
+        let record = parse_record(
+            "type=PATH msg=audit(1716188570.002:4569): item=1 name=6576696C206A6F62 nametype=CREATE\x1dOUID=\"root\"",
+        )
+        .unwrap();
+        assert_eq!(record.kind, "PATH");
+        assert_eq!(record.serial, 4569);
+        assert_eq!(record.fields["name"], "evil job");
+        assert_eq!(record.fields["OUID"], "root");
+        assert_eq!(record.timestamp.timestamp_millis(), 1716188570002);
+    }
+
+    #[test]
+    fn test_events() {
+        let contents = std::fs::read_to_string("test/audit.log").unwrap();
+        let events = EventAssembler::default().process_contents(&contents);
+        assert_eq!(events.len(), 8);
+        assert_eq!(events[0].records.len(), 5);
+        assert_eq!(events[0].syscall(), Some("openat"));
+        assert!(events[0].is_modifying());
+        assert!(!events[1].is_modifying());
+    }
+
+    #[test]
+    fn test_alerts() {
+        let alerts = alerts();
+        assert_eq!(alerts.len(), 6);
+        assert_eq!(
+            alerts[0],
+            AuditAlert::FileChange {
+                path: PathBuf::from("/etc/passwd"),
+                kind: ChangeKind::Modified,
+                actor: Actor {
+                    pid: Some("2817".to_string()),
+                    auid: Some("1000".to_string()),
+                    uid: Some("0".to_string()),
+                    exe: Some("/usr/bin/vim.basic".to_string()),
+                    cmdline: Some("vim /etc/passwd".to_string()),
+                },
+                timestamp: DateTime::from_timestamp(1716188561, 123_000_000).unwrap(),
+            }
+        );
+        let message: MessageBackend = (&alerts[1]).into();
+        assert_eq!(message.title, "File created");
+        assert_eq!(
+            message.body,
+            "<code>/etc/cron.d/evil job</code>\n\npid: 3012\nauid: alice (1001)\nuid: root (0)\nexe: /usr/bin/touch\ncmdline: touch evil job"
+        );
+        let message: MessageBackend = (&alerts[2]).into();
+        assert_eq!(message.title, "Command executed (rootcmd)");
+        assert!(message.body.starts_with("<code>nc -lvp 4444</code>"));
+        let message: MessageBackend = (&alerts[3]).into();
+        assert_eq!(message.title, "Failed login of root");
+        assert_eq!(message.severity, Severity::Warning);
+        let message: MessageBackend = (&alerts[4]).into();
+        assert_eq!(message.title, "Login of alice (1001)");
+        assert!(message.body.contains("from <code>198.51.100.4</code>"));
+        let message: MessageBackend = (&alerts[5]).into();
+        assert_eq!(message.title, "File removed");
+        assert!(message
+            .body
+            .starts_with("<code>/etc/sudoers.d/backdoor</code>\n"));
+    }
+
+    #[test]
+    fn test_rename_paths() {
+        let mut config = Config::demo_config();
+        config.directories = vec!["/etc".to_string()];
+        // mv cron.d/a cron.daily/a in /etc
+        let contents = [
+            "type=SYSCALL msg=audit(1716188561.123:20): arch=c000003e syscall=82 success=yes",
+            "type=CWD msg=audit(1716188561.123:20): cwd=\"/etc\"",
+            "type=PATH msg=audit(1716188561.123:20): item=0 name=\"cron.d/\" nametype=PARENT",
+            "type=PATH msg=audit(1716188561.123:20): item=1 name=\"cron.daily/\" nametype=PARENT",
+            "type=PATH msg=audit(1716188561.123:20): item=2 name=\"cron.d/a\" nametype=DELETE",
+            "type=PATH msg=audit(1716188561.123:20): item=3 name=\"cron.daily/a\" nametype=CREATE",
+            "type=EOE msg=audit(1716188561.123:20):",
+        ]
+        .join("\n");
+        let events = EventAssembler::default().process_contents(&contents);
+        let paths: Vec<(PathBuf, ChangeKind)> = AuditAlert::from_event(&events[0], &config)
+            .into_iter()
+            .map(|alert| match alert {
+                AuditAlert::FileChange { path, kind, .. } => (path, kind),
+                _ => panic!("unexpected alert {alert:?}"),
+            })
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                (PathBuf::from("/etc/cron.d/a"), ChangeKind::Removed),
+                (PathBuf::from("/etc/cron.daily/a"), ChangeKind::Created),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_escaped_messages() {
+        let actor = Actor {
+            pid: Some("1".to_string()),
+            auid: None,
+            uid: None,
+            exe: None,
+            cmdline: Some("touch <b>".to_string()),
+        };
+        let timestamp = DateTime::from_timestamp(1716188561, 0).unwrap();
+        let message: MessageBackend = (&AuditAlert::Login {
+            account: "<i>root".to_string(),
+            address: Some("198.51.100.4".to_string()),
+            success: false,
+            actor: actor.clone(),
+            timestamp,
+        })
+            .into();
+        assert_eq!(message.title, "Failed login of &lt;i&gt;root");
+        assert!(message.body.starts_with("<b>&lt;i&gt;root</b>"));
+        assert!(message.body.contains("cmdline: touch &lt;b&gt;"));
+        let message: MessageBackend = (&AuditAlert::FileChange {
+            path: PathBuf::from("/etc/cron.d/<a href=x>"),
+            kind: ChangeKind::Created,
+            actor,
+            timestamp,
+        })
+            .into();
+        assert!(message
+            .body
+            .starts_with("<code>/etc/cron.d/&lt;a href=x&gt;</code>"));
+    }
+
+    #[test]
+    fn test_interleaved_events() {
+        let lines = [
+            "type=SYSCALL msg=audit(1716188561.123:10): syscall=257 success=yes",
+            "type=SYSCALL msg=audit(1716188561.124:11): syscall=87 success=yes",
+            "type=PATH msg=audit(1716188561.123:10): item=0 name=\"/etc/a\" nametype=CREATE",
+            "type=PATH msg=audit(1716188561.124:11): item=0 name=\"/etc/b\" nametype=DELETE",
+            "type=EOE msg=audit(1716188561.124:11):",
+            "type=SYSCALL msg=audit(1716188562.000:12): syscall=2 success=yes",
+        ];
+        let mut assembler = EventAssembler::default();
+        let events = assembler.process_contents(&lines.join("\n"));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].records.len(), 2);
+        assert_eq!(events[0].records[1].fields["name"], "/etc/b");
+
+        // the event without EOE is reported once a later record exceeds the timeout
+        let events = assembler
+            .process_contents("type=SYSCALL msg=audit(1716188570.000:13): syscall=2 success=yes");
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].records[0].serial, 10);
+        assert_eq!(events[0].records[1].fields["name"], "/etc/a");
+        assert_eq!(events[1].records[0].serial, 12);
+    }
+}
@@ -1,22 +1,61 @@
-use std::str::from_utf8;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 use std::string::String;
 use std::time::Duration;
 
+use chrono::{DateTime, Datelike, FixedOffset, Local, NaiveDateTime, TimeDelta, TimeZone, Utc};
 use lazy_static::lazy_static;
 use regex::{Captures, Regex};
-use tokio::fs::File;
-use tokio::io::SeekFrom;
-use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use sled::Db;
+use thiserror::Error;
 use tokio::time;
 
+use crate::authorized_keys;
+use crate::brute_force::BruteForceDetector;
 use crate::config::Config;
-use crate::dispatcher::{MessageBackend, SnitchDispatcher};
+use crate::dispatcher::{escape_html, MessageBackend, Severity, SnitchDispatcher};
+use crate::enrichment::{self, GeoIp};
+use crate::journal::{Journal, JournalEntry};
+use crate::known_logins;
+use crate::persist::{open_watcher_database, PersistError};
+use crate::rules::CompiledRule;
+use crate::sessions::{self, Session, Sessions, SessionsError};
+use crate::tail::{rotated_path, LogTail};
 
 static INTERVAL: u64 = 1000;
 
-#[derive(Debug)]
+/// Name of the database of the watcher holding log offsets, known logins and the journal cursor.
+static WATCHER_NAME: &str = "authentication";
+
+/// sshd processes without a failure line for this long are forgotten when deduplicating failed
+/// logins. Exceeds the default `LoginGraceTime` of sshd.
+static SSHD_CONNECTION_TTL: i64 = 600;
+
+/// Time sudo prompts for passwords until it gives up, three times its default
+/// `passwd_timeout`.
+static SUDO_PROMPT_TTL: i64 = 900;
+
+/// Tree of the watcher databases holding the file ids and read positions of log files.
+static OFFSETS_TREE: &str = "log_offsets";
+
+/// Key of the journal cursor in the offsets tree.
+static JOURNAL_CURSOR_KEY: &str = "journal";
+
+#[derive(Debug, Error)]
 pub enum WatchLogsError {
+    #[error("no authentication log file configured")]
     NoLogFile,
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+    #[error(transparent)]
+    Regex(#[from] regex::Error),
+    #[error(transparent)]
+    GeoIp(#[from] maxminddb::MaxMindDBError),
+    #[error(transparent)]
+    Persist(#[from] PersistError),
+    #[error(transparent)]
+    Sessions(#[from] SessionsError),
 }
 
 /// Watch authentication logs and dispatch a [Notification](notifiers::Notification) if a login was registered.
@@ -25,34 +64,364 @@ pub async fn watch_authentication_logs(
     config: &Config,
 ) -> Result<(), WatchLogsError> {
     info!("start watching authentication logs");
+    let state = open_watcher_database(config, WATCHER_NAME)?;
+    let mut sessions = Sessions::load(config)?;
+    match sessions.prune(Local::now().fixed_offset()) {
+        Ok(pruned) => {
+            for session in pruned.iter() {
+                info!("dropped stale session {session}");
+            }
+        }
+        Err(e) => warn!("failed dropping stale sessions: {e}"),
+    }
+    let monitor = AuthenticationMonitor::new(config, state.clone(), sessions)?;
+    if config.authentication_journal {
+        return watch_journal(dispatcher, config, &state, monitor).await;
+    }
     let filename = match config.authentication_logs.as_deref() {
         None => return Err(WatchLogsError::NoLogFile),
         Some(v) => v,
     };
-    let mut file = File::open(filename).await.unwrap();
+    watch_log_file(dispatcher, config, &state, monitor, filename).await
+}
+
+/// Follow the authentication log `filename`.
+async fn watch_log_file(
+    dispatcher: &SnitchDispatcher,
+    config: &Config,
+    state: &Db,
+    mut monitor: AuthenticationMonitor,
+    filename: &Path,
+) -> Result<(), WatchLogsError> {
+    let mut tail = open_log(state, config, filename).await?;
+    let mut interval = time::interval(Duration::from_millis(INTERVAL));
+    let mut rule_files = RuleFile::from_config(config)?;
+
+    loop {
+        poll_rule_files(&mut rule_files, state, config, dispatcher).await;
+
+        let contents = match tail.read_lines().await {
+            Ok(contents) => contents,
+            Err(err) => {
+                warn!("failed reading {:?}: {err}", filename);
+                interval.tick().await;
+                continue;
+            }
+        };
+        if !contents.is_empty() {
+            monitor
+                .process_contents(dispatcher, config, &contents)
+                .await;
+            let _ = save_offset(state, filename, tail.offset())
+                .inspect_err(|e| warn!("failed saving log offset: {e}"));
+        }
+
+        interval.tick().await;
+    }
+}
+
+/// Remember the logins in the authentication logs including the last rotated file, so that the
+/// first login after `--init` of a user with a known source and method is not reported as
+/// unusual. Returns the number of logins found.
+pub async fn seed_known_logins(config: &Config) -> Result<usize, WatchLogsError> {
+    let contents = match (config.authentication_journal, &config.authentication_logs) {
+        (true, _) => Journal::history()
+            .await?
+            .iter()
+            .filter_map(JournalEntry::to_syslog_line)
+            .collect::<Vec<String>>()
+            .join("\n"),
+        (false, Some(filename)) => {
+            let mut contents = String::new();
+            for path in [rotated_path(filename), filename.clone()] {
+                match tokio::fs::read(&path).await {
+                    Ok(data) => contents.push_str(&String::from_utf8_lossy(&data)),
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            contents
+        }
+        (false, None) => return Err(WatchLogsError::NoLogFile),
+    };
+    let state = open_watcher_database(config, WATCHER_NAME)?;
+    let logins = find_logins(&contents);
+    for login in logins.iter() {
+        known_logins::assess_login(
+            config,
+            &state,
+            &login.hostname,
+            &login.username,
+            login.ip,
+            login.remote_host.as_deref(),
+            &login.method,
+        )?;
+    }
+    state.flush().map_err(PersistError::from)?;
+    Ok(logins.len())
+}
+
+/// Watch sshd and sudo entries of the systemd journal. Entries are parsed like lines of
+/// authentication log files.
+async fn watch_journal(
+    dispatcher: &SnitchDispatcher,
+    config: &Config,
+    state: &Db,
+    mut monitor: AuthenticationMonitor,
+) -> Result<(), WatchLogsError> {
+    let cursor = load_journal_cursor(state)
+        .inspect_err(|e| warn!("failed loading journal cursor: {e}"))
+        .ok()
+        .flatten();
+    if let Some(cursor) = cursor.as_deref() {
+        info!("resuming journal after {cursor}");
+    }
+    let mut journal = Journal::follow(cursor.as_deref())?;
+    let mut rule_files = RuleFile::from_config(config)?;
     let mut interval = time::interval(Duration::from_millis(INTERVAL));
-    let mut contents = vec![];
-    let mut position = file.read_to_end(&mut contents).await.unwrap();
 
     loop {
-        contents.truncate(0);
-        file.seek(SeekFrom::Start(position as u64))
+        let entries = tokio::select! {
+            entries = journal.read_entries() => entries?,
+            _ = interval.tick() => {
+                poll_rule_files(&mut rule_files, state, config, dispatcher).await;
+                continue;
+            }
+        };
+        let contents = entries
+            .iter()
+            .filter_map(JournalEntry::to_syslog_line)
+            .collect::<Vec<String>>()
+            .join("\n");
+        monitor
+            .process_contents(dispatcher, config, &contents)
+            .await;
+        if let Some(cursor) = entries.iter().rev().find_map(JournalEntry::cursor) {
+            let _ = save_journal_cursor(state, cursor)
+                .inspect_err(|e| warn!("failed saving journal cursor: {e}"));
+        }
+    }
+}
+
+/// A log file with the custom rules that are evaluated on it.
+struct RuleFile {
+    path: PathBuf,
+    /// `None` until the file could be opened.
+    tail: Option<LogTail>,
+    rules: Vec<CompiledRule>,
+}
+
+impl RuleFile {
+    /// Group the custom rules of `config` that apply to other files than the authentication
+    /// logs by file.
+    fn from_config(config: &Config) -> Result<Vec<RuleFile>, regex::Error> {
+        let mut rule_files: Vec<RuleFile> = vec![];
+        for rule in config.rules.iter() {
+            let Some(path) = rule.file.as_ref() else {
+                continue;
+            };
+            let rule = CompiledRule::new(rule)?;
+            match rule_files
+                .iter_mut()
+                .find(|rule_file| &rule_file.path == path)
+            {
+                Some(rule_file) => rule_file.rules.push(rule),
+                None => rule_files.push(RuleFile {
+                    path: path.clone(),
+                    tail: None,
+                    rules: vec![rule],
+                }),
+            }
+        }
+        Ok(rule_files)
+    }
+}
+
+/// Read new lines of the files of custom rules and dispatch a message for each match.
+async fn poll_rule_files(
+    rule_files: &mut [RuleFile],
+    state: &Db,
+    config: &Config,
+    dispatcher: &SnitchDispatcher,
+) {
+    for rule_file in rule_files.iter_mut() {
+        if rule_file.tail.is_none() {
+            rule_file.tail = open_log(state, config, &rule_file.path)
+                .await
+                .inspect_err(|e| debug!("cannot open {:?}: {e}", rule_file.path))
+                .ok();
+        }
+        let Some(tail) = rule_file.tail.as_mut() else {
+            continue;
+        };
+        let contents = match tail.read_lines().await {
+            Ok(contents) => contents,
+            Err(err) => {
+                warn!("failed reading {:?}: {err}", rule_file.path);
+                continue;
+            }
+        };
+        if contents.is_empty() {
+            continue;
+        }
+        for rule in rule_file.rules.iter() {
+            dispatch_rule_matches(rule, dispatcher, &contents).await;
+        }
+        let _ = save_offset(state, &rule_file.path, tail.offset())
+            .inspect_err(|e| warn!("failed saving log offset: {e}"));
+    }
+}
+
+async fn dispatch_rule_matches(rule: &CompiledRule, dispatcher: &SnitchDispatcher, contents: &str) {
+    for message in rule.find_matches(contents) {
+        info!("rule {} matched: {}", rule.rule.name, message.title);
+        let _ = dispatcher
+            .dispatch(message)
             .await
-            .expect("Failed seeking log position!");
-        position += file.read_to_end(&mut contents).await.unwrap();
+            .inspect_err(|e| error!("{:?}", e));
+    }
+}
 
-        let contents_str = from_utf8(&contents).unwrap();
+/// Keeps the state needed to interpret authentication events across reads.
+struct AuthenticationMonitor {
+    brute_force: BruteForceDetector,
+    failed_logins: FailedLoginDeduplicator,
+    sudo_failures: SudoFailureDeduplicator,
+    /// Custom rules evaluated on the authentication logs.
+    rules: Vec<CompiledRule>,
+    geoip: GeoIp,
+    /// Database of the watcher holding the known logins.
+    state: Db,
+    sessions: Sessions,
+}
+
+impl AuthenticationMonitor {
+    fn new(config: &Config, state: Db, sessions: Sessions) -> Result<Self, WatchLogsError> {
+        let rules = config
+            .rules
+            .iter()
+            .filter(|rule| rule.file.is_none())
+            .map(CompiledRule::new)
+            .collect::<Result<Vec<CompiledRule>, regex::Error>>()?;
+        Ok(AuthenticationMonitor {
+            brute_force: BruteForceDetector::new(config.brute_force.clone()),
+            failed_logins: FailedLoginDeduplicator::default(),
+            sudo_failures: SudoFailureDeduplicator::default(),
+            rules,
+            geoip: GeoIp::from_config(config)?,
+            state,
+            sessions,
+        })
+    }
 
-        let logins = find_logins(contents_str);
+    /// Find authentication events in `contents` and dispatch them.
+    async fn process_contents(
+        &mut self,
+        dispatcher: &SnitchDispatcher,
+        config: &Config,
+        contents: &str,
+    ) {
+        for failed_login in find_failed_logins(contents) {
+            if !self.failed_logins.is_new(&failed_login) {
+                continue;
+            }
+            debug!("failed login {:?}", failed_login);
+            let brute_force_attempts = self.brute_force.record_failure(
+                failed_login.source().as_deref(),
+                failed_login.username.as_deref(),
+                failed_login.datetime.with_timezone(&Utc),
+            );
+            for brute_force in brute_force_attempts.iter() {
+                warn!("brute force {:?}", brute_force);
+                let _ = dispatcher
+                    .dispatch(brute_force.into())
+                    .await
+                    .inspect_err(|e| error!("{:?}", e));
+            }
+        }
+
+        let logins = find_logins(contents);
+        let hostnames =
+            enrichment::resolve_hostnames(config, logins.iter().filter_map(|login| login.ip)).await;
         for login in logins.iter() {
             info!("logins {:?}", login);
+            let mut message: MessageBackend = login.into();
+            match known_logins::assess_login(
+                config,
+                &self.state,
+                &login.hostname,
+                &login.username,
+                login.ip,
+                login.remote_host.as_deref(),
+                &login.method,
+            ) {
+                Ok(assessment) => {
+                    message.body.push_str(&format!("\n{assessment}"));
+                    message = message.with_severity(assessment.severity());
+                }
+                Err(e) => error!("cannot assess login: {:?}", e),
+            }
+            if let Some(ip) = login.ip {
+                let enrichment = enrichment::enrich(&self.geoip, ip, &hostnames);
+                message.body.push_str(&format!("\n{enrichment}"));
+            }
+            if let Some(fingerprint) = &login.fingerprint {
+                match authorized_keys::lookup(config, &login.username, fingerprint) {
+                    Ok(key_info) => {
+                        if let Some(comment) = key_info.comment {
+                            message
+                                .body
+                                .push_str(&format!("\nkey comment: <code>{comment}</code>"));
+                        }
+                        if key_info.in_baseline == Some(false) {
+                            message.title = "Login with unknown key".to_string();
+                            message.severity = message.severity.max(Severity::High);
+                        }
+                    }
+                    Err(e) => error!("cannot look up key {fingerprint}: {:?}", e),
+                }
+            }
+            if self
+                .brute_force
+                .is_attacker(&login.source(), login.datetime.with_timezone(&Utc))
+            {
+                message.title = "Login after brute force attempt".to_string();
+                message = message.with_severity(Severity::Critical);
+            }
             let _ = dispatcher
-                .dispatch(login.into())
+                .dispatch(message)
                 .await
                 .inspect_err(|e| error!("{:?}", e));
+
+            let session = Session {
+                hostname: login.hostname.clone(),
+                pid: login.pid,
+                username: login.username.clone(),
+                source: login.source(),
+                method: login.method.clone(),
+                opened: login.datetime,
+            };
+            let _ = self
+                .sessions
+                .open(session)
+                .inspect_err(|e| error!("cannot store session: {:?}", e));
+        }
+
+        for logout in find_logouts(contents).iter() {
+            info!("logout {:?}", logout);
+            match self.sessions.close(&logout.hostname, logout.pid) {
+                Ok(Some(session)) => {
+                    let _ = dispatcher
+                        .dispatch(logout.to_message(&session))
+                        .await
+                        .inspect_err(|e| error!("{:?}", e));
+                }
+                Ok(None) => debug!("logout of unknown session {:?}", logout),
+                Err(e) => error!("cannot close session: {:?}", e),
+            }
         }
 
-        let root_elevations = find_root_elevations(contents_str);
+        let root_elevations = find_root_elevations(contents);
 
         for root_elevations in root_elevations.iter() {
             info!("root elevation {:?}", root_elevations);
@@ -62,21 +431,107 @@ pub async fn watch_authentication_logs(
                 .inspect_err(|e| error!("{:?}", e));
         }
 
-        interval.tick().await;
+        for sudo_event in find_sudo_events(contents).iter() {
+            if !self.sudo_failures.is_new(sudo_event) {
+                continue;
+            }
+            info!("sudo {:?}", sudo_event);
+            let _ = dispatcher
+                .dispatch(sudo_event.into())
+                .await
+                .inspect_err(|e| error!("{:?}", e));
+        }
+
+        for account_event in find_account_events(contents).iter() {
+            info!("account change {:?}", account_event);
+            let _ = dispatcher
+                .dispatch(account_event.into())
+                .await
+                .inspect_err(|e| error!("{:?}", e));
+        }
+
+        for rule in self.rules.iter() {
+            dispatch_rule_matches(rule, dispatcher, contents).await;
+        }
     }
 }
 
+/// Open `filename` at the offset stored in the watcher database `state` to process entries that
+/// were written while snitch was not running. Starts at the end of the file if there is none.
+pub(crate) async fn open_log(
+    state: &Db,
+    config: &Config,
+    filename: &Path,
+) -> std::io::Result<LogTail> {
+    let offset = load_offset(state, filename)
+        .inspect_err(|e| warn!("failed loading log offset: {e}"))
+        .ok()
+        .flatten();
+    match offset {
+        Some((id, position)) => {
+            info!("resuming {:?} at byte {position}", filename);
+            LogTail::open_at(filename, id, position, config.log_replay_limit).await
+        }
+        None => LogTail::open_at_end(filename).await,
+    }
+}
+
+fn load_offset(state: &Db, filename: &Path) -> Result<Option<(u64, u64)>, PersistError> {
+    let offset = state
+        .open_tree(OFFSETS_TREE)?
+        .get(filename.to_string_lossy().as_bytes())?
+        .filter(|value| value.len() == 16)
+        .map(|value| {
+            let (id, position) = value.split_at(8);
+            (
+                u64::from_be_bytes(id.try_into().unwrap()),
+                u64::from_be_bytes(position.try_into().unwrap()),
+            )
+        });
+    Ok(offset)
+}
+
+pub(crate) fn save_offset(
+    state: &Db,
+    filename: &Path,
+    offset: (u64, u64),
+) -> Result<(), PersistError> {
+    let mut value = offset.0.to_be_bytes().to_vec();
+    value.extend_from_slice(&offset.1.to_be_bytes());
+    state
+        .open_tree(OFFSETS_TREE)?
+        .insert(filename.to_string_lossy().as_bytes(), value)?;
+    state.flush()?;
+    Ok(())
+}
+
+fn load_journal_cursor(state: &Db) -> Result<Option<String>, PersistError> {
+    let cursor = state
+        .open_tree(OFFSETS_TREE)?
+        .get(JOURNAL_CURSOR_KEY)?
+        .map(|value| String::from_utf8_lossy(&value).into_owned());
+    Ok(cursor)
+}
+
+fn save_journal_cursor(state: &Db, cursor: &str) -> Result<(), PersistError> {
+    state
+        .open_tree(OFFSETS_TREE)?
+        .insert(JOURNAL_CURSOR_KEY, cursor.as_bytes())?;
+    state.flush()?;
+    Ok(())
+}
+
 #[derive(Debug)]
 struct RootElevation {
     username: String,
-    datetime: String,
+    datetime: DateTime<FixedOffset>,
     hostname: String,
 }
 
 impl RootElevation {
     fn from_capture(cap: &Captures) -> RootElevation {
         let username = cap["username"].to_owned();
-        let datetime = cap["datetime"].to_owned();
+        let datetime = parse_timestamp(&cap["datetime"], Local::now());
         let hostname = cap["hostname"].to_owned();
         RootElevation {
             username,
@@ -96,49 +551,538 @@ impl From<&RootElevation> for MessageBackend {
     }
 }
 
+/// Timestamp and hostname at the start of syslog lines. Timestamps are either in the classic BSD
+/// format (`May 20 05:27:28`) or in RFC 3339 format (`2024-05-20T05:27:28.123456+02:00`).
+static SYSLOG_PREFIX: &str = r"(?P<datetime>[A-Z][a-z]{2} +\d{1,2} \d{1,2}:\d{2}:\d{2}|\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}(?:\.\d+)?(?:Z|[+-]\d{2}:\d{2})) (?P<hostname>\S+)";
+
+/// Parse a syslog timestamp. BSD timestamps lack year and timezone. They are assumed to be in
+/// local time of the current year or, if that would be in the future, of the previous year.
+fn parse_timestamp(timestamp: &str, now: DateTime<Local>) -> DateTime<FixedOffset> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(timestamp) {
+        return datetime;
+    }
+    let timestamp = timestamp
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ");
+    let parse_in_year = |year: i32| {
+        NaiveDateTime::parse_from_str(&format!("{year} {timestamp}"), "%Y %b %d %H:%M:%S")
+            .ok()
+            .and_then(|naive| Local.from_local_datetime(&naive).earliest())
+    };
+    match parse_in_year(now.year()) {
+        Some(datetime) if datetime <= now + TimeDelta::days(1) => datetime.fixed_offset(),
+        _ => parse_in_year(now.year() - 1)
+            .map(|datetime| datetime.fixed_offset())
+            .unwrap_or_else(|| {
+                warn!("cannot parse timestamp {timestamp}");
+                now.fixed_offset()
+            }),
+    }
+}
+
+/// Parse the source address logged by sshd. IPv4-mapped IPv6 addresses are converted to IPv4.
+/// sshd logs the reverse resolved hostname instead if `UseDNS` is enabled which is returned as
+/// the second element.
+fn parse_source(address: &str) -> (Option<IpAddr>, Option<String>) {
+    let address = address.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = address.parse::<IpAddr>() {
+        return (Some(ip.to_canonical()), None);
+    }
+    let is_hostname = !address.is_empty()
+        && address
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_');
+    match is_hostname {
+        true => (None, Some(address.to_lowercase())),
+        false => {
+            warn!("cannot parse source address {address}");
+            (None, None)
+        }
+    }
+}
+
+/// Format a source address for messages and as key to group events by.
+fn format_source(ip: &Option<IpAddr>, remote_host: &Option<String>) -> Option<String> {
+    ip.map(|ip| ip.to_string()).or(remote_host.clone())
+}
+
 #[allow(dead_code)]
 #[derive(Debug)]
 struct Login {
+    /// Pid of the sshd process handling the session.
+    pid: u32,
     username: String,
-    ip: String,
-    datetime: String,
+    ip: Option<IpAddr>,
+    remote_host: Option<String>,
+    datetime: DateTime<FixedOffset>,
     hostname: String,
     method: String,
+    /// Type and fingerprint of the key used for `publickey` logins.
+    key_type: Option<String>,
+    fingerprint: Option<String>,
 }
 
 impl Login {
     fn from_capture(cap: &Captures) -> Login {
         let username = cap["username"].to_owned();
-        let ip = cap["ipaddress"].to_owned();
-        let datetime = cap["datetime"].to_owned();
+        let (ip, remote_host) = parse_source(&cap["ipaddress"]);
+        let datetime = parse_timestamp(&cap["datetime"], Local::now());
         let hostname = cap["hostname"].to_owned();
         let method = cap["method"].to_owned();
+        let key_type = cap.name("key_type").map(|m| m.as_str().to_owned());
+        let fingerprint = cap.name("fingerprint").map(|m| m.as_str().to_owned());
         Login {
+            pid: cap["pid"].parse().unwrap_or_default(),
             username,
             ip,
+            remote_host,
             datetime,
             hostname,
             method,
+            key_type,
+            fingerprint,
         }
     }
+
+    fn source(&self) -> String {
+        format_source(&self.ip, &self.remote_host).unwrap_or("unknown".to_string())
+    }
 }
 
 impl From<&Login> for MessageBackend {
     fn from(value: &Login) -> Self {
+        let mut body = format!(
+            "User <b>{}</b> just logged in from <code>{}</code> using <code>{}</code>\n{}",
+            value.username,
+            value.source(),
+            value.method,
+            value.datetime
+        );
+        if let (Some(key_type), Some(fingerprint)) = (&value.key_type, &value.fingerprint) {
+            body.push_str(&format!("\nkey: <code>{key_type} {fingerprint}</code>"));
+        }
+        MessageBackend::new_now("Login detected".to_string(), body)
+    }
+}
+
+/// The end of an ssh session.
+#[derive(Debug)]
+struct Logout {
+    pid: u32,
+    username: String,
+    datetime: DateTime<FixedOffset>,
+    hostname: String,
+}
+
+impl Logout {
+    fn from_capture(cap: &Captures) -> Logout {
+        Logout {
+            pid: cap["pid"].parse().unwrap_or_default(),
+            username: cap["username"].to_owned(),
+            datetime: parse_timestamp(&cap["datetime"], Local::now()),
+            hostname: cap["hostname"].to_owned(),
+        }
+    }
+
+    fn to_message(&self, session: &Session) -> MessageBackend {
+        MessageBackend::new_now(
+            "Logout detected".to_string(),
+            format!(
+                "User <b>{}</b> logged out from <code>{}</code> after {}\n{}",
+                self.username,
+                session.source,
+                sessions::format_duration(self.datetime - session.opened),
+                self.datetime
+            ),
+        )
+        .with_severity(Severity::Info)
+    }
+}
+
+/// Finds the ends of ssh sessions in authentication logs
+fn find_logouts(contents: &str) -> Vec<Logout> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(&(SYSLOG_PREFIX.to_owned() + r" sshd\[(?P<pid>\d+)\]: pam_unix\(sshd:session\): session closed for user (?P<username>\S+)")).unwrap();
+    }
+    RE.captures_iter(contents)
+        .map(|cap| Logout::from_capture(&cap))
+        .collect()
+}
+
+/// A failed authentication. Depending on the log line the username or source address is unknown.
+#[allow(dead_code)]
+#[derive(Debug)]
+struct FailedLogin {
+    pid: u32,
+    username: Option<String>,
+    ip: Option<IpAddr>,
+    remote_host: Option<String>,
+    datetime: DateTime<FixedOffset>,
+    hostname: String,
+    reason: String,
+}
+
+impl FailedLogin {
+    fn from_capture(cap: &Captures) -> FailedLogin {
+        let non_empty = |name| {
+            cap.name(name)
+                .map(|m| m.as_str().to_owned())
+                .filter(|value| !value.is_empty())
+        };
+        let (ip, remote_host) = match non_empty("ipaddress") {
+            Some(address) => parse_source(&address),
+            None => (None, None),
+        };
+        FailedLogin {
+            pid: cap["pid"].parse().unwrap_or_default(),
+            username: non_empty("username"),
+            ip,
+            remote_host,
+            datetime: parse_timestamp(&cap["datetime"], Local::now()),
+            hostname: cap["hostname"].to_owned(),
+            reason: cap["reason"].to_owned(),
+        }
+    }
+
+    fn source(&self) -> Option<String> {
+        format_source(&self.ip, &self.remote_host)
+    }
+}
+
+/// Counts each failed ssh authentication once. sshd logs an attempt on several lines, e.g.
+/// `Invalid user` or the `authentication failure` of pam_unix before `Failed password`, and
+/// `Connection closed by authenticating user` after it.
+#[derive(Debug, Default)]
+struct FailedLoginDeduplicator {
+    /// The time of the last failure line of each sshd process and whether a failure was counted
+    /// that was not yet followed by its `Failed` line.
+    connections: HashMap<u32, (DateTime<FixedOffset>, bool)>,
+}
+
+impl FailedLoginDeduplicator {
+    /// Returns `false` if `failed_login` belongs to an attempt that was already counted.
+    fn is_new(&mut self, failed_login: &FailedLogin) -> bool {
+        let time = failed_login.datetime;
+        self.connections
+            .retain(|_, (last, _)| *last > time - TimeDelta::seconds(SSHD_CONNECTION_TTL));
+        let previous = self
+            .connections
+            .get(&failed_login.pid)
+            .map(|(_, pending)| *pending);
+        let (is_new, pending) = if failed_login.reason.starts_with("Failed") {
+            // completes an attempt counted by a preceding line
+            (previous != Some(true), false)
+        } else if failed_login.reason.starts_with("Connection closed") {
+            // only a disconnect without any failure line is an attempt of its own
+            (previous.is_none(), false)
+        } else {
+            (previous != Some(true), true)
+        };
+        self.connections.insert(failed_login.pid, (time, pending));
+        is_new
+    }
+}
+
+/// Finds failed ssh authentications in authentication logs
+fn find_failed_logins(contents: &str) -> Vec<FailedLogin> {
+    lazy_static! {
+        static ref RES: Vec<Regex> = [
+            r"(?P<reason>Failed \w+|Invalid user) (?:for )?(?:invalid user )?(?P<username>\S*) from (?P<ipaddress>\S+)",
+            r"pam_unix\(sshd:auth\): (?P<reason>authentication failure);.* rhost=(?P<ipaddress>\S*)(?:\s+user=(?P<username>\S+))?",
+            r"(?P<reason>Connection closed by authenticating user) (?P<username>\S+) (?P<ipaddress>\S+) port \d+ \[preauth\]",
+        ]
+        .iter()
+        .map(|pattern| {
+            Regex::new(&format!(r"(?m)^{SYSLOG_PREFIX} sshd\[(?P<pid>\d+)\]: {pattern}"))
+                .unwrap()
+        })
+        .collect();
+    }
+    let mut failed_logins: Vec<(usize, FailedLogin)> = RES
+        .iter()
+        .flat_map(|re| re.captures_iter(contents))
+        .map(|cap| (cap.get(0).unwrap().start(), FailedLogin::from_capture(&cap)))
+        .collect();
+    // keep the order of the log
+    failed_logins.sort_by_key(|(start, _)| *start);
+    failed_logins
+        .into_iter()
+        .map(|(_, failed_login)| failed_login)
+        .collect()
+}
+
+#[derive(Debug, PartialEq)]
+enum SudoEventKind {
+    Command,
+    AuthenticationFailure,
+    NotInSudoers,
+    /// Any other incident logged by sudo, e.g. `command not allowed`.
+    Denied(String),
+}
+
+/// A command run through sudo or a failed attempt to do so.
+#[allow(dead_code)]
+#[derive(Debug)]
+struct SudoEvent {
+    kind: SudoEventKind,
+    username: String,
+    /// User the command is run as. Unknown for failed authentications logged by pam.
+    target: Option<String>,
+    command: Option<String>,
+    tty: Option<String>,
+    pwd: Option<String>,
+    datetime: DateTime<FixedOffset>,
+    hostname: String,
+}
+
+impl SudoEvent {
+    fn from_command_capture(cap: &Captures) -> SudoEvent {
+        let kind = match cap.name("incident").map(|m| m.as_str()) {
+            None => SudoEventKind::Command,
+            Some("user NOT in sudoers") => SudoEventKind::NotInSudoers,
+            Some(incident)
+                if incident.ends_with("incorrect password attempt")
+                    || incident.ends_with("incorrect password attempts") =>
+            {
+                SudoEventKind::AuthenticationFailure
+            }
+            Some(incident) => SudoEventKind::Denied(incident.to_owned()),
+        };
+        SudoEvent {
+            kind,
+            username: cap["username"].to_owned(),
+            target: Some(cap["target"].to_owned()),
+            command: Some(cap["command"].to_owned()),
+            tty: Some(cap["tty"].to_owned()),
+            pwd: Some(cap["pwd"].to_owned()),
+            datetime: parse_timestamp(&cap["datetime"], Local::now()),
+            hostname: cap["hostname"].to_owned(),
+        }
+    }
+
+    fn from_auth_failure_capture(cap: &Captures) -> SudoEvent {
+        SudoEvent {
+            kind: SudoEventKind::AuthenticationFailure,
+            username: cap["username"].to_owned(),
+            // pam reports the authenticating user, not the target of the command
+            target: None,
+            command: None,
+            tty: Some(cap["tty"].to_owned()).filter(|tty| !tty.is_empty()),
+            pwd: None,
+            datetime: parse_timestamp(&cap["datetime"], Local::now()),
+            hostname: cap["hostname"].to_owned(),
+        }
+    }
+}
+
+impl From<&SudoEvent> for MessageBackend {
+    fn from(value: &SudoEvent) -> Self {
+        let (title, severity) = match &value.kind {
+            SudoEventKind::Command => ("Sudo command".to_string(), Severity::Warning),
+            SudoEventKind::AuthenticationFailure => {
+                ("Sudo authentication failure".to_string(), Severity::Warning)
+            }
+            SudoEventKind::NotInSudoers => ("User not in sudoers".to_string(), Severity::High),
+            SudoEventKind::Denied(incident) => (format!("Sudo denied: {incident}"), Severity::High),
+        };
+        let mut body = format!("User <b>{}</b>", value.username);
+        if let Some(target) = &value.target {
+            body.push_str(&format!(" as <b>{target}</b>"));
+        }
+        body.push_str(&format!(" on <code>{}</code>", value.hostname));
+        if let Some(command) = &value.command {
+            body.push_str(&format!("\ncommand: <code>{}</code>", escape_html(command)));
+        }
+        if let Some(pwd) = &value.pwd {
+            body.push_str(&format!("\npwd: <code>{}</code>", escape_html(pwd)));
+        }
+        if let Some(tty) = &value.tty {
+            body.push_str(&format!("\ntty: {tty}"));
+        }
+        body.push_str(&format!("\n{}", value.datetime));
+        MessageBackend::new_now(title, body).with_severity(severity)
+    }
+}
+
+/// Reports each failed sudo authentication once. With pam_unix, sudo logs an `authentication
+/// failure` at the first wrong password and `N incorrect password attempts` when it gives up.
+#[derive(Debug, Default)]
+struct SudoFailureDeduplicator {
+    /// Time of the last authentication failure logged by pam for each user.
+    failures: HashMap<String, DateTime<FixedOffset>>,
+}
+
+impl SudoFailureDeduplicator {
+    /// Returns `false` if `event` concludes a failure that was already reported.
+    fn is_new(&mut self, event: &SudoEvent) -> bool {
+        if event.kind != SudoEventKind::AuthenticationFailure {
+            return true;
+        }
+        let time = event.datetime;
+        self.failures
+            .retain(|_, last| *last > time - TimeDelta::seconds(SUDO_PROMPT_TTL));
+        match event.target {
+            // logged by pam
+            None => {
+                self.failures.insert(event.username.clone(), time);
+                true
+            }
+            Some(_) => self.failures.remove(&event.username).is_none(),
+        }
+    }
+}
+
+/// Finds commands and failures logged by sudo in authentication logs
+fn find_sudo_events(contents: &str) -> Vec<SudoEvent> {
+    lazy_static! {
+        static ref COMMAND_RE: Regex = Regex::new(&format!(
+            r"(?m)^{SYSLOG_PREFIX} sudo(?:\[\d+\])?: +(?P<username>\S+) : (?:(?P<incident>[^;=]+?) ; )?TTY=(?P<tty>\S+) ; PWD=(?P<pwd>.*?) ; USER=(?P<target>\S+) ;.*? COMMAND=(?P<command>.*)$"
+        ))
+        .unwrap();
+        static ref AUTH_FAILURE_RE: Regex = Regex::new(&format!(
+            r"(?m)^{SYSLOG_PREFIX} sudo(?:\[\d+\])?: pam_unix\(sudo:auth\): authentication failure;.* tty=(?P<tty>\S*) ruser=(?P<username>\S+) rhost="
+        ))
+        .unwrap();
+    }
+    let mut events: Vec<(usize, SudoEvent)> = COMMAND_RE
+        .captures_iter(contents)
+        .map(|cap| {
+            (
+                cap.get(0).unwrap().start(),
+                SudoEvent::from_command_capture(&cap),
+            )
+        })
+        .chain(AUTH_FAILURE_RE.captures_iter(contents).map(|cap| {
+            (
+                cap.get(0).unwrap().start(),
+                SudoEvent::from_auth_failure_capture(&cap),
+            )
+        }))
+        .collect();
+    // keep the order of the log
+    events.sort_by_key(|(start, _)| *start);
+    events.into_iter().map(|(_, event)| event).collect()
+}
+
+/// A user or group change logged by the shadow utilities.
+#[allow(dead_code)]
+#[derive(Debug)]
+struct AccountEvent {
+    description: String,
+    severity: Severity,
+    datetime: DateTime<FixedOffset>,
+    hostname: String,
+}
+
+impl From<&AccountEvent> for MessageBackend {
+    fn from(value: &AccountEvent) -> Self {
         MessageBackend::new_now(
-            "Login detected".to_string(),
+            "Account change".to_string(),
             format!(
-                "User <b>{}</b> just logged in from <code>{}</code> using <code>{}</code>\n{}",
-                value.username, value.ip, value.method, value.datetime
+                "{} on <code>{}</code>\n{}",
+                value.description, value.hostname, value.datetime
             ),
         )
+        .with_severity(value.severity)
     }
 }
 
+type Describe = fn(&Captures) -> (String, Severity);
+
+/// Finds user and group changes in authentication logs
+fn find_account_events(contents: &str) -> Vec<AccountEvent> {
+    lazy_static! {
+        static ref RES: Vec<(Regex, Describe)> = {
+            let patterns: [(&str, Describe); 6] = [
+                (
+                    r"useradd\[\d+\]: new user: name=(?P<name>[^,]+), UID=(?P<uid>\d+), GID=(?P<gid>\d+), home=(?P<home>[^,]+), shell=(?P<shell>[^,\s]+)",
+                    |cap| {
+                        let severity = match &cap["uid"] {
+                            "0" => Severity::Critical,
+                            _ => Severity::High,
+                        };
+                        (
+                            format!(
+                                "User <b>{}</b> added with UID {} (home: <code>{}</code>, shell: <code>{}</code>)",
+                                &cap["name"], &cap["uid"], &cap["home"], &cap["shell"]
+                            ),
+                            severity,
+                        )
+                    },
+                ),
+                (r"userdel\[\d+\]: delete user '(?P<name>[^']+)'", |cap| {
+                    (
+                        format!("User <b>{}</b> removed", &cap["name"]),
+                        Severity::Warning,
+                    )
+                }),
+                (r"usermod\[\d+\]: (?P<change>.+)", |cap| {
+                    (
+                        format!("usermod: {}", escape_html(&cap["change"])),
+                        Severity::High,
+                    )
+                }),
+                (
+                    r"groupadd\[\d+\]: new group: name=(?P<name>[^,]+), GID=(?P<gid>\d+)",
+                    |cap| {
+                        (
+                            format!(
+                                "Group <b>{}</b> added with GID {}",
+                                &cap["name"], &cap["gid"]
+                            ),
+                            Severity::Warning,
+                        )
+                    },
+                ),
+                (r"groupdel\[\d+\]: group '(?P<name>[^']+)' removed", |cap| {
+                    (
+                        format!("Group <b>{}</b> removed", &cap["name"]),
+                        Severity::Warning,
+                    )
+                }),
+                (
+                    r"(?:passwd|chpasswd)\[\d+\]: pam_unix\(\w+:chauthtok\): password changed for (?P<name>\S+)",
+                    |cap| {
+                        (
+                            format!("Password of user <b>{}</b> changed", &cap["name"]),
+                            Severity::Warning,
+                        )
+                    },
+                ),
+            ];
+            patterns
+                .into_iter()
+                .map(|(pattern, describe)| {
+                    let re = Regex::new(&format!(r"(?m)^{SYSLOG_PREFIX} {pattern}")).unwrap();
+                    (re, describe)
+                })
+                .collect()
+        };
+    }
+    let mut events: Vec<(usize, AccountEvent)> = RES
+        .iter()
+        .flat_map(|(re, describe)| re.captures_iter(contents).map(move |cap| (cap, describe)))
+        .map(|(cap, describe)| {
+            let (description, severity) = describe(&cap);
+            let event = AccountEvent {
+                description,
+                severity,
+                datetime: parse_timestamp(&cap["datetime"], Local::now()),
+                hostname: cap["hostname"].to_owned(),
+            };
+            (cap.get(0).unwrap().start(), event)
+        })
+        .collect();
+    // keep the order of the log
+    events.sort_by_key(|(start, _)| *start);
+    events.into_iter().map(|(_, event)| event).collect()
+}
+
 /// Finds logins in authentication logs
 fn find_root_elevations(contents: &str) -> Vec<RootElevation> {
     lazy_static! {
-        static ref RE: Regex = Regex::new(r"(?P<datetime>\D{3,4} \d{1,2} \d{1,2}:\d{2}:\d{2}) (?P<hostname>.+) sudo: pam_unix\(sudo:session\): session opened for user root by (?P<username>.*)").unwrap();
+        static ref RE: Regex = Regex::new(&(SYSLOG_PREFIX.to_owned() + r" sudo: pam_unix\(sudo:session\): session opened for user root by (?P<username>.*)")).unwrap();
     }
     RE.captures_iter(contents)
         .map(|cap| RootElevation::from_capture(&cap))
@@ -148,7 +1092,7 @@ fn find_root_elevations(contents: &str) -> Vec<RootElevation> {
 /// Finds logins in authentication logs
 fn find_logins(contents: &str) -> Vec<Login> {
     lazy_static! {
-        static ref RE: Regex = Regex::new(r"(?P<datetime>\D{3,4} \d{1,2} \d{1,2}:\d{2}:\d{2}) (?P<hostname>.+) (?P<process>sshd\[\d+\]):.* Accepted (?P<method>\w+) for (?P<username>.*) from (?P<ipaddress>\d{1,3}.\d{1,3}.\d{1,3}.\d{1,3}) port (?P<port>\d+)").unwrap();
+        static ref RE: Regex = Regex::new(&(SYSLOG_PREFIX.to_owned() + r" sshd\[(?P<pid>\d+)\]:.* Accepted (?P<method>\w+) for (?P<username>.*) from (?P<ipaddress>\S+) port (?P<port>\d+)(?: ssh2: (?P<key_type>\S+) (?P<fingerprint>SHA256:\S+))?")).unwrap();
     }
     RE.captures_iter(contents)
         .map(|cap| Login::from_capture(&cap))
@@ -163,8 +1107,153 @@ async fn parse_test() {
     let test_file = Path::new("test/auth.log");
     let data = fs::read_to_string(test_file).unwrap();
     let logins = find_logins(&data);
-    assert_eq!(logins.len(), 2);
+    assert_eq!(logins.len(), 6);
+    assert_eq!(logins[0].key_type.as_deref(), Some("RSA"));
+    assert_eq!(
+        logins[0].fingerprint.as_deref(),
+        Some("SHA256:ilijsdf+/lijsdf//sdflijjidj1123E5gKg")
+    );
+    assert_eq!(logins[1].fingerprint, None);
+
+    let logouts = find_logouts(&data);
+    assert_eq!(logouts.len(), 1);
+    assert_eq!(logouts[0].pid, logins[1].pid);
+    assert_eq!(
+        sessions::format_duration(logouts[0].datetime - logins[1].datetime),
+        "1h 35m 13s"
+    );
+    assert_eq!(logins[2].ip, Some("2001:db8::42".parse().unwrap()));
+    assert_eq!(logins[3].ip, Some("12.23.43.12".parse().unwrap()));
+    assert_eq!(logins[4].ip, None);
+    assert_eq!(
+        logins[4].remote_host.as_deref(),
+        Some("host-12.example.com")
+    );
 
     let root_elevations = find_root_elevations(&data);
     assert_eq!(root_elevations.len(), 1);
+
+    let sudo_events = find_sudo_events(&data);
+    assert_eq!(sudo_events.len(), 4);
+    assert_eq!(sudo_events[0].kind, SudoEventKind::Command);
+    assert_eq!(
+        sudo_events[0].command.as_deref(),
+        Some("/usr/bin/apt update")
+    );
+    assert_eq!(sudo_events[1].kind, SudoEventKind::AuthenticationFailure);
+    assert_eq!(sudo_events[1].target, None);
+    assert_eq!(sudo_events[2].kind, SudoEventKind::AuthenticationFailure);
+    assert_eq!(sudo_events[2].target.as_deref(), Some("root"));
+    assert_eq!(sudo_events[3].kind, SudoEventKind::NotInSudoers);
+    let mut deduplicator = SudoFailureDeduplicator::default();
+    let reported: Vec<&SudoEvent> = sudo_events
+        .iter()
+        .filter(|event| deduplicator.is_new(event))
+        .collect();
+    assert_eq!(reported.len(), 3);
+    assert_eq!(
+        MessageBackend::from(reported[1]).body.lines().next(),
+        Some("User <b>user1</b> on <code>robust-test</code>")
+    );
+
+    let account_events = find_account_events(&data);
+    assert_eq!(account_events.len(), 3);
+    assert_eq!(account_events[0].severity, Severity::Critical);
+    assert_eq!(
+        account_events[0].description,
+        "User <b>mallory</b> added with UID 0 (home: <code>/home/mallory</code>, shell: <code>/bin/bash</code>)"
+    );
+
+    let failed_logins = find_failed_logins(&data);
+    assert_eq!(failed_logins.len(), 5);
+    assert!(failed_logins
+        .iter()
+        .all(|failed_login| failed_login.source().as_deref() == Some("203.0.113.7")));
+}
+
+#[test]
+fn deduplicate_failed_logins_test() {
+    // an invalid user, two wrong passwords for root and a disconnect after a rejected key
+    let reads = [
+        "May 20 06:01:12 host sshd[16001]: Invalid user admin from 203.0.113.7 port 51234\n",
+        "May 20 06:01:14 host sshd[16001]: Failed password for invalid user admin from 203.0.113.7 port 51234 ssh2\n\
+        May 20 06:01:20 host sshd[16002]: pam_unix(sshd:auth): authentication failure; logname= uid=0 euid=0 tty=ssh ruser= rhost=203.0.113.7  user=root\n\
+        May 20 06:01:22 host sshd[16002]: Failed password for root from 203.0.113.7 port 51240 ssh2\n\
+        May 20 06:01:25 host sshd[16002]: Failed password for root from 203.0.113.7 port 51240 ssh2\n",
+        "May 20 06:01:26 host sshd[16002]: Connection closed by authenticating user root 203.0.113.7 port 51240 [preauth]\n\
+        May 20 06:01:30 host sshd[16003]: Connection closed by authenticating user root 203.0.113.7 port 51250 [preauth]\n",
+    ];
+    let mut deduplicator = FailedLoginDeduplicator::default();
+    let counted: Vec<(u32, Option<String>)> = reads
+        .iter()
+        .flat_map(|contents| find_failed_logins(contents))
+        .filter(|failed_login| deduplicator.is_new(failed_login))
+        .map(|failed_login| (failed_login.pid, failed_login.username))
+        .collect();
+    assert_eq!(
+        counted,
+        vec![
+            (16001, Some("admin".to_string())),
+            (16002, Some("root".to_string())),
+            (16002, Some("root".to_string())),
+            (16003, Some("root".to_string())),
+        ]
+    );
+}
+
+#[test]
+fn parse_timestamp_test() {
+    let now = Local.with_ymd_and_hms(2025, 1, 2, 10, 0, 0).unwrap();
+    let datetime = parse_timestamp("2024-05-20T05:27:28.123456+02:00", now);
+    assert_eq!(datetime.offset().local_minus_utc(), 7200);
+    assert_eq!(datetime.timestamp_subsec_micros(), 123456);
+
+    let datetime = parse_timestamp("Jan  2 09:00:00", now);
+    assert_eq!(datetime.year(), 2025);
+    // logs from december read in january are from the previous year
+    let datetime = parse_timestamp("Dec 31 23:59:59", now);
+    assert_eq!(datetime.year(), 2024);
+}
+
+#[test]
+fn parse_journal_test() {
+    use crate::journal::ExportParser;
+
+    let data = std::fs::read("test/journal.export").unwrap();
+    let contents = ExportParser::default()
+        .feed(&data)
+        .iter()
+        .filter_map(JournalEntry::to_syslog_line)
+        .collect::<Vec<String>>()
+        .join("\n");
+    assert_eq!(find_logins(&contents).len(), 1);
+    assert_eq!(find_root_elevations(&contents).len(), 1);
+}
+
+#[tokio::test]
+async fn seed_known_logins_test() {
+    use crate::known_logins::Assessment;
+
+    let directory = tempfile::tempdir().unwrap();
+    let mut config = Config::demo_config();
+    config.snitch_root = directory.path().to_string_lossy().into_owned();
+    config.authentication_journal = false;
+    config.authentication_logs = Some(PathBuf::from("test/auth.log"));
+    assert_eq!(seed_known_logins(&config).await.unwrap(), 6);
+
+    let state = open_watcher_database(&config, WATCHER_NAME).unwrap();
+    let assess = |ip: &str, method: &str| {
+        known_logins::assess_login(
+            &config,
+            &state,
+            "blabla-hostname",
+            "demo-user",
+            ip.parse().ok(),
+            None,
+            method,
+        )
+        .unwrap()
+    };
+    assert_eq!(assess("12.23.43.99", "publickey"), Assessment::Known);
+    assert_eq!(assess("198.51.100.4", "publickey"), Assessment::NewSource);
 }
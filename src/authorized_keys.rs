@@ -0,0 +1,430 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use data_encoding::{BASE64, BASE64_NOPAD};
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::accounts::{self, User};
+use crate::config::Config;
+use crate::dispatcher::{MessageBackend, Severity};
+use crate::persist::{open_state_database, PersistError};
+
+/// Tree of the state database holding the keys of all `authorized_keys` files of the baseline.
+/// It is not part of the hash database so that the authentication watcher can read it while
+/// the file watcher holds the hash database open.
+static AUTHORIZED_KEYS_TREE: &str = "authorized_keys";
+
+/// Files sshd reads authorized keys from, relative to the home directory.
+static AUTHORIZED_KEYS_FILES: [&str; 2] = [".ssh/authorized_keys", ".ssh/authorized_keys2"];
+
+/// Key types as written in `authorized_keys` files.
+static KEY_TYPES: [&str; 8] = [
+    "ssh-rsa",
+    "ssh-dss",
+    "ssh-ed25519",
+    "ecdsa-sha2-nistp256",
+    "ecdsa-sha2-nistp384",
+    "ecdsa-sha2-nistp521",
+    "sk-ssh-ed25519@openssh.com",
+    "sk-ecdsa-sha2-nistp256@openssh.com",
+];
+
+#[derive(Debug, Error)]
+pub enum AuthorizedKeysError {
+    #[error(transparent)]
+    Sled(#[from] sled::Error),
+    #[error(transparent)]
+    Persist(#[from] PersistError),
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// A public key line of an `authorized_keys` file.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct AuthorizedKey {
+    pub key_type: String,
+    /// SHA256 fingerprint formatted like sshd logs it, e.g. `SHA256:jif0eIX1...`.
+    pub fingerprint: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub options: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+/// The SHA256 fingerprint of a base64 encoded public key.
+pub fn fingerprint(encoded_key: &str) -> Option<String> {
+    let key = BASE64.decode(encoded_key.as_bytes()).ok()?;
+    Some(format!(
+        "SHA256:{}",
+        BASE64_NOPAD.encode(digest(&SHA256, &key).as_ref())
+    ))
+}
+
+/// Split `line` at whitespace outside of double quotes which may appear in key options.
+fn split_fields(line: &str) -> Vec<&str> {
+    let mut fields = vec![];
+    let mut start = None;
+    let mut quoted = false;
+    for (index, character) in line.char_indices() {
+        match character {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if let Some(start) = start.take() {
+                    fields.push(&line[start..index]);
+                }
+                continue;
+            }
+            _ => {}
+        }
+        start.get_or_insert(index);
+    }
+    if let Some(start) = start {
+        fields.push(&line[start..]);
+    }
+    fields
+}
+
+fn parse_line(line: &str) -> Option<AuthorizedKey> {
+    let fields = split_fields(line);
+    let position = fields.iter().position(|field| KEY_TYPES.contains(field))?;
+    let options = match position {
+        0 => None,
+        _ => Some(fields[..position].join(" ")),
+    };
+    let comment = fields.get(position + 2..).filter(|rest| !rest.is_empty());
+    Some(AuthorizedKey {
+        key_type: fields[position].to_string(),
+        fingerprint: fingerprint(fields.get(position + 1)?)?,
+        options,
+        comment: comment.map(|comment| comment.join(" ")),
+    })
+}
+
+/// Parse the keys of an `authorized_keys` file. Comments and invalid lines are skipped.
+pub fn parse(contents: &str) -> Vec<AuthorizedKey> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(parse_line)
+        .collect()
+}
+
+/// Returns `true` if `path` is an `authorized_keys` file in the home directory of an account in
+/// `/etc/passwd`. Copies like the one in `/etc/skel` are ordinary files.
+pub fn is_authorized_keys_file(path: &Path) -> bool {
+    AUTHORIZED_KEYS_FILES
+        .iter()
+        .any(|file| path.ends_with(file))
+        && read_users().is_ok_and(|users| is_user_file(&users, path))
+}
+
+fn is_user_file(users: &BTreeMap<String, User>, path: &Path) -> bool {
+    users
+        .values()
+        .any(|user| user_files(user).iter().any(|file| file == path))
+}
+
+/// The `authorized_keys` files of `user`, whether they exist or not.
+pub fn user_files(user: &User) -> Vec<PathBuf> {
+    AUTHORIZED_KEYS_FILES
+        .iter()
+        .map(|file| Path::new(&user.home).join(file))
+        .collect()
+}
+
+fn read_users() -> Result<BTreeMap<String, User>, std::io::Error> {
+    Ok(accounts::parse_passwd(&std::fs::read_to_string(
+        accounts::PASSWD,
+    )?))
+}
+
+/// The `authorized_keys` files of all users in `/etc/passwd`, whether they exist or not.
+pub fn all_user_files() -> Result<BTreeSet<PathBuf>, std::io::Error> {
+    Ok(read_users()?.values().flat_map(user_files).collect())
+}
+
+/// Read the keys of `path`. A missing file has no keys.
+fn read_keys(path: &Path) -> Result<Vec<AuthorizedKey>, std::io::Error> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Ok(parse(&contents)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e),
+    }
+}
+
+/// Store the keys of the `authorized_keys` files of all users as the baseline, replacing the
+/// previous one.
+pub fn record_baseline(config: &Config) -> Result<(), AuthorizedKeysError> {
+    let db = open_state_database(config)?;
+    let tree = db.open_tree(AUTHORIZED_KEYS_TREE)?;
+    tree.clear()?;
+    for path in all_user_files()? {
+        if !path.exists() {
+            continue;
+        }
+        match read_keys(&path) {
+            Ok(keys) => {
+                tree.insert(
+                    path.to_string_lossy().as_bytes(),
+                    serde_json::to_vec(&keys)?,
+                )?;
+            }
+            Err(e) => warn!("cannot read {path:?}: {e}"),
+        }
+    }
+    db.flush()?;
+    Ok(())
+}
+
+/// What is known about the key a user logged in with.
+#[derive(Debug, Default, PartialEq)]
+pub struct KeyInfo {
+    /// Comment of the key in the user's current `authorized_keys` if readable.
+    pub comment: Option<String>,
+    /// Whether any `authorized_keys` of the baseline contains the key. `None` if no baseline
+    /// was recorded.
+    pub in_baseline: Option<bool>,
+}
+
+/// Look up the key with `fingerprint` which `username` logged in with.
+pub fn lookup(
+    config: &Config,
+    username: &str,
+    fingerprint: &str,
+) -> Result<KeyInfo, AuthorizedKeysError> {
+    let mut info = KeyInfo::default();
+    if let Some(user) = read_users()?.get(username) {
+        info.comment = user_files(user)
+            .iter()
+            .filter_map(|path| std::fs::read_to_string(path).ok())
+            .flat_map(|contents| parse(&contents))
+            .find(|key| key.fingerprint == fingerprint)
+            .and_then(|key| key.comment);
+    }
+
+    let db = open_state_database(config)?;
+    let tree = db.open_tree(AUTHORIZED_KEYS_TREE)?;
+    if !tree.is_empty() {
+        let mut in_baseline = false;
+        for entry in tree.iter() {
+            let (_, keys) = entry?;
+            let keys: Vec<AuthorizedKey> = serde_json::from_slice(&keys)?;
+            if keys.iter().any(|key| key.fingerprint == fingerprint) {
+                in_baseline = true;
+                break;
+            }
+        }
+        info.in_baseline = Some(in_baseline);
+    }
+    Ok(info)
+}
+
+/// A change of the keys in an `authorized_keys` file compared to the baseline.
+#[derive(Debug, PartialEq)]
+pub enum KeyChange {
+    Added {
+        path: PathBuf,
+        key: AuthorizedKey,
+    },
+    Removed {
+        path: PathBuf,
+        key: AuthorizedKey,
+    },
+    /// The options restricting the key changed, e.g. a `from=` pattern was removed.
+    OptionsChanged {
+        path: PathBuf,
+        old: AuthorizedKey,
+        new: AuthorizedKey,
+    },
+}
+
+impl KeyChange {
+    pub fn severity(&self) -> Severity {
+        match self {
+            KeyChange::Added { .. } | KeyChange::OptionsChanged { .. } => Severity::High,
+            KeyChange::Removed { .. } => Severity::Warning,
+        }
+    }
+}
+
+fn describe(key: &AuthorizedKey) -> String {
+    match &key.comment {
+        Some(comment) => format!(
+            "<code>{} {}</code> ({comment})",
+            key.key_type, key.fingerprint
+        ),
+        None => format!("<code>{} {}</code>", key.key_type, key.fingerprint),
+    }
+}
+
+impl fmt::Display for KeyChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeyChange::Added { path, key } => {
+                write!(f, "Key {} added to {}", describe(key), path.display())
+            }
+            KeyChange::Removed { path, key } => {
+                write!(f, "Key {} removed from {}", describe(key), path.display())
+            }
+            KeyChange::OptionsChanged { path, old, new } => write!(
+                f,
+                "Options of key {} in {} changed: <code>{}</code> → <code>{}</code>",
+                describe(new),
+                path.display(),
+                old.options.as_deref().unwrap_or_default(),
+                new.options.as_deref().unwrap_or_default()
+            ),
+        }
+    }
+}
+
+impl From<&KeyChange> for MessageBackend {
+    fn from(value: &KeyChange) -> Self {
+        MessageBackend::new_now("Authorized keys changed".to_string(), value.to_string())
+            .with_severity(value.severity())
+    }
+}
+
+/// Compare the keys of `path` by fingerprint.
+fn diff(path: &Path, old: &[AuthorizedKey], new: &[AuthorizedKey]) -> Vec<KeyChange> {
+    let find = |keys: &[AuthorizedKey], key: &AuthorizedKey| {
+        keys.iter()
+            .find(|other| other.fingerprint == key.fingerprint)
+            .cloned()
+    };
+    let mut changes = vec![];
+    for key in new {
+        match find(old, key) {
+            None => changes.push(KeyChange::Added {
+                path: path.to_path_buf(),
+                key: key.clone(),
+            }),
+            Some(old) if old.options != key.options => changes.push(KeyChange::OptionsChanged {
+                path: path.to_path_buf(),
+                old,
+                new: key.clone(),
+            }),
+            Some(_) => {}
+        }
+    }
+    for key in old {
+        if find(new, key).is_none() {
+            changes.push(KeyChange::Removed {
+                path: path.to_path_buf(),
+                key: key.clone(),
+            });
+        }
+    }
+    changes
+}
+
+fn diff_tree(tree: &sled::Tree, path: &Path) -> Result<Vec<KeyChange>, AuthorizedKeysError> {
+    let key = path.to_string_lossy();
+    let old: Vec<AuthorizedKey> = match tree.get(key.as_bytes())? {
+        Some(old) => serde_json::from_slice(&old)?,
+        None => vec![],
+    };
+    Ok(diff(path, &old, &read_keys(path)?))
+}
+
+/// Compare the keys of the `authorized_keys` file at `path` with the baseline. The baseline is
+/// only replaced by `--init`.
+pub fn diff_file(config: &Config, path: &Path) -> Result<Vec<KeyChange>, AuthorizedKeysError> {
+    let db = open_state_database(config)?;
+    diff_tree(&db.open_tree(AUTHORIZED_KEYS_TREE)?, path)
+}
+
+/// Compare the `authorized_keys` files of all users and the files of the baseline with the
+/// baseline.
+pub fn scan(config: &Config) -> Result<Vec<KeyChange>, AuthorizedKeysError> {
+    let db = open_state_database(config)?;
+    let tree = db.open_tree(AUTHORIZED_KEYS_TREE)?;
+    let mut paths = all_user_files()?;
+    for entry in tree.iter() {
+        let (key, _) = entry?;
+        paths.insert(PathBuf::from(String::from_utf8_lossy(&key).into_owned()));
+    }
+    let mut changes = vec![];
+    for path in paths {
+        changes.extend(diff_tree(&tree, &path)?);
+    }
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let contents = std::fs::read_to_string("test/authorized_keys").unwrap();
+        let keys = parse(&contents);
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].key_type, "ssh-ed25519");
+        assert_eq!(
+            keys[0].fingerprint,
+            "SHA256:jif0eIX1492AWyWGqRDoE3HmxM+UH+/iGolpnRcPNCs"
+        );
+        assert_eq!(keys[0].comment.as_deref(), Some("alice@laptop"));
+        assert_eq!(keys[0].options, None);
+
+        assert_eq!(keys[1].key_type, "ssh-rsa");
+        assert_eq!(
+            keys[1].fingerprint,
+            "SHA256:QjhpcwgADXTGiiAFdt1DmpqFa53y/5YMWzd6BEEIGCE"
+        );
+        assert_eq!(
+            keys[1].options.as_deref(),
+            Some("from=\"10.0.0.0/8\",command=\"/usr/local/bin/deploy\",no-pty")
+        );
+        assert_eq!(keys[1].comment.as_deref(), Some("deploy"));
+    }
+
+    #[test]
+    fn test_diff() {
+        let contents = std::fs::read_to_string("test/authorized_keys").unwrap();
+        let old = parse(&contents);
+        let mut new = old.clone();
+        let removed = new.remove(0);
+        new[0].options = None;
+        let path = Path::new("/root/.ssh/authorized_keys");
+        let users = accounts::parse_passwd(
+            "root:x:0:0:root:/root:/bin/bash\nalice:x:1000:1000::/home/alice:/bin/bash\n",
+        );
+        assert!(is_user_file(&users, path));
+        assert!(is_user_file(
+            &users,
+            Path::new("/home/alice/.ssh/authorized_keys2")
+        ));
+        assert!(!is_user_file(
+            &users,
+            Path::new("/etc/skel/.ssh/authorized_keys")
+        ));
+
+        let changes = diff(path, &old, &new);
+        assert_eq!(changes.len(), 2);
+        assert!(
+            matches!(&changes[0], KeyChange::OptionsChanged { new, .. } if new.options.is_none())
+        );
+        assert_eq!(
+            changes[1],
+            KeyChange::Removed {
+                path: path.to_path_buf(),
+                key: removed.clone()
+            }
+        );
+        assert_eq!(
+            diff(path, &[], std::slice::from_ref(&removed)),
+            vec![KeyChange::Added {
+                path: path.to_path_buf(),
+                key: removed
+            }]
+        );
+    }
+}
@@ -10,20 +10,42 @@ use eyre::{Result, WrapErr};
 use log::LevelFilter;
 
 use crate::authentication_logs::watch_authentication_logs;
-use crate::cli::Cli;
+use crate::cli::{Cli, Command, SuidCommand};
 use crate::dispatcher::{ConfigBackend, SnitchDispatcher};
 use crate::hashing::{init_hash_db, watch_files};
 use clap::Parser;
 
 use crate::config::{load_config_from_file, print_basic_config};
 use crate::persist::validate_hashes;
+mod accounts;
+mod audit;
 mod authentication_logs;
+mod authorized_keys;
+mod brute_force;
 mod cli;
 mod config;
+mod containers;
 mod dispatcher;
+mod enrichment;
+#[cfg(target_os = "linux")]
+mod fanotify;
+mod file_attributes;
 mod hashing;
+mod journal;
+mod known_logins;
+mod network;
+mod permissions;
 mod persist;
+mod persistence;
+#[cfg(target_os = "linux")]
+mod processes;
+#[cfg(target_os = "linux")]
+mod rootkit;
+mod rules;
+mod sessions;
 mod style;
+mod system;
+mod tail;
 mod test_utils;
 
 fn setup_logging(args: &Cli) {
@@ -58,7 +80,16 @@ async fn main() -> Result<()> {
     let start = Instant::now();
 
     debug!("start!");
-    if args.init {
+    if let Some(command) = args.command {
+        match command {
+            Command::Sessions => {
+                sessions::print_sessions(&config).wrap_err("failed reading sessions")?
+            }
+            Command::Suid {
+                command: SuidCommand::List,
+            } => permissions::print_inventory(&config),
+        }
+    } else if args.init {
         config.clear_database();
         init_hash_db(&config, &dispatcher)
             .await
@@ -76,11 +107,21 @@ async fn main() -> Result<()> {
             })
             .expect("Checking files failed");
     } else if args.watch_files {
-        watch_files(&config, &dispatcher).await;
+        watch_files(&config, &dispatcher)
+            .await
+            .expect("failed starting file watching");
     } else if args.watch_authentications {
         watch_authentication_logs(&dispatcher, &config)
             .await
             .expect("failed starting log file watching");
+    } else if args.watch_audit {
+        audit::watch_audit_log(&dispatcher, &config)
+            .await
+            .expect("failed starting audit log watching");
+    } else if args.watch_listeners {
+        network::watch_listeners(&config, &dispatcher).await;
+    } else if args.watch_system {
+        system::watch_system(&config, &dispatcher).await;
     } else if args.send_test_message {
         dispatcher
             .send_test_message()
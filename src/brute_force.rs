@@ -0,0 +1,182 @@
+use std::collections::{HashMap, VecDeque};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::dispatcher::{escape_html, MessageBackend, Severity};
+
+/// How long a source of a brute force attempt is remembered to escalate later logins.
+const ESCALATION_PERIOD_HOURS: i64 = 24;
+
+/// Thresholds of failed authentications which are reported as brute force attempts.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct BruteForceConfig {
+    /// Number of failed authentications within `window`.
+    pub threshold: usize,
+    /// Length of the sliding window in seconds.
+    pub window: u64,
+}
+
+impl Default for BruteForceConfig {
+    fn default() -> Self {
+        BruteForceConfig {
+            threshold: 10,
+            window: 60,
+        }
+    }
+}
+
+/// What a brute force attempt was grouped by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    SourceAddress,
+    Username,
+}
+
+/// A brute force attempt in progress.
+#[derive(Debug, PartialEq)]
+pub struct BruteForce {
+    pub target: Target,
+    pub key: String,
+    pub failures: usize,
+    pub window: u64,
+}
+
+impl From<&BruteForce> for MessageBackend {
+    fn from(value: &BruteForce) -> Self {
+        let target = match value.target {
+            Target::SourceAddress => format!("from <code>{}</code>", escape_html(&value.key)),
+            Target::Username => format!("for user <b>{}</b>", escape_html(&value.key)),
+        };
+        MessageBackend::new_now(
+            "Brute force in progress".to_string(),
+            format!(
+                "{} failed authentications {target} within {}s",
+                value.failures, value.window
+            ),
+        )
+        .with_severity(Severity::High)
+    }
+}
+
+/// Counts failed authentications per source address and per username in a sliding window.
+pub struct BruteForceDetector {
+    config: BruteForceConfig,
+    by_address: HashMap<String, VecDeque<DateTime<Utc>>>,
+    by_username: HashMap<String, VecDeque<DateTime<Utc>>>,
+    /// Source addresses of brute force attempts and when they were detected.
+    attackers: HashMap<String, DateTime<Utc>>,
+}
+
+impl BruteForceDetector {
+    pub fn new(config: BruteForceConfig) -> Self {
+        BruteForceDetector {
+            config,
+            by_address: HashMap::new(),
+            by_username: HashMap::new(),
+            attackers: HashMap::new(),
+        }
+    }
+
+    /// Register a failed authentication at `time`. Returns the brute force attempts whose
+    /// threshold was crossed by it.
+    pub fn record_failure(
+        &mut self,
+        address: Option<&str>,
+        username: Option<&str>,
+        time: DateTime<Utc>,
+    ) -> Vec<BruteForce> {
+        let window = Duration::seconds(self.config.window as i64);
+        let mut detected = vec![];
+        for (target, key) in [
+            (Target::SourceAddress, address),
+            (Target::Username, username),
+        ] {
+            let Some(key) = key else {
+                continue;
+            };
+            let counters = match target {
+                Target::SourceAddress => &mut self.by_address,
+                Target::Username => &mut self.by_username,
+            };
+            let failures = counters.entry(key.to_string()).or_default();
+            failures.push_back(time);
+            while failures
+                .front()
+                .is_some_and(|first| *first <= time - window)
+            {
+                failures.pop_front();
+            }
+            if failures.len() == self.config.threshold {
+                detected.push(BruteForce {
+                    target,
+                    key: key.to_string(),
+                    failures: failures.len(),
+                    window: self.config.window,
+                });
+                if target == Target::SourceAddress {
+                    self.attackers.insert(key.to_string(), time);
+                }
+            }
+        }
+        self.evict(time - window);
+        detected
+    }
+
+    /// Returns `true` if a brute force attempt originated from `address` recently.
+    pub fn is_attacker(&self, address: &str, time: DateTime<Utc>) -> bool {
+        self.attackers
+            .get(address)
+            .is_some_and(|detected| time - *detected < Duration::hours(ESCALATION_PERIOD_HOURS))
+    }
+
+    /// Drop counters without failures after `oldest` to bound memory usage.
+    fn evict(&mut self, oldest: DateTime<Utc>) {
+        for counters in [&mut self.by_address, &mut self.by_username] {
+            counters.retain(|_, failures| failures.back().is_some_and(|last| *last > oldest));
+        }
+        let escalation_period = Duration::hours(ESCALATION_PERIOD_HOURS);
+        self.attackers
+            .retain(|_, detected| *detected > oldest - escalation_period);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_threshold_and_window() {
+        let mut detector = BruteForceDetector::new(BruteForceConfig {
+            threshold: 3,
+            window: 10,
+        });
+        let start = Utc::now();
+        let at = |seconds| start + Duration::seconds(seconds);
+
+        assert!(detector
+            .record_failure(Some("1.2.3.4"), Some("root"), at(0))
+            .is_empty());
+        assert!(detector
+            .record_failure(Some("1.2.3.4"), Some("admin"), at(5))
+            .is_empty());
+        // first failure dropped out of the window
+        assert!(detector
+            .record_failure(Some("1.2.3.4"), Some("root"), at(11))
+            .is_empty());
+        let detected = detector.record_failure(Some("1.2.3.4"), Some("root"), at(12));
+        assert_eq!(detected.len(), 1);
+        assert_eq!(detected[0].target, Target::SourceAddress);
+        assert!(detector.is_attacker("1.2.3.4", at(100)));
+        assert!(!detector.is_attacker("4.3.2.1", at(100)));
+
+        // usernames of invalid users are chosen by the client
+        let mut detector = BruteForceDetector::new(BruteForceConfig {
+            threshold: 1,
+            window: 10,
+        });
+        let detected = detector.record_failure(None, Some("<a href=x>"), at(0));
+        let message: MessageBackend = (&detected[0]).into();
+        assert!(message.body.contains("for user <b>&lt;a href=x&gt;</b>"));
+    }
+}
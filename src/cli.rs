@@ -1,4 +1,4 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 static DEFAULT_CONFIG: &str = "/etc/snitch/config.yaml";
 
@@ -7,6 +7,9 @@ static DEFAULT_CONFIG: &str = "/etc/snitch/config.yaml";
 #[clap(author, version, about, long_about = None)]
 #[command(arg_required_else_help = true)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Print a demo configuration (e.g. as a template for /etc/snitch/config.yaml)
     #[clap(long)]
     pub demo_config: bool,
@@ -35,7 +38,36 @@ pub struct Cli {
     #[clap(long)]
     pub watch_authentications: bool,
 
+    /// Watch the Linux audit log for modifications, commands and logins
+    #[clap(long)]
+    pub watch_audit: bool,
+
+    /// Watch for new network listeners
+    #[clap(long)]
+    pub watch_listeners: bool,
+
+    /// Watch loaded kernel modules and boot files
+    #[clap(long)]
+    pub watch_system: bool,
+
     /// Verbose mode
     #[clap(short, long)]
     pub verbose: bool,
 }
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// List the ssh sessions which are currently open
+    Sessions,
+    /// Inspect setuid, setgid and world-writable files
+    Suid {
+        #[command(subcommand)]
+        command: SuidCommand,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum SuidCommand {
+    /// List the setuid, setgid and world-writable files below the configured directories
+    List,
+}
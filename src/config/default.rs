@@ -15,8 +15,24 @@ pub fn get_config() -> Config {
         ],
         sender: Sender::example(),
         authentication_logs: None,
+        audit_log: None,
+        authentication_journal: false,
         snitch_root: "/etc/snitch".to_owned(),
         url: Config::default_url(),
         token: "SDFOIJSDFOIJSDFOIJ".to_string(),
+        fanotify: false,
+        poll_interval: Config::default_poll_interval(),
+        log_replay_limit: Config::default_log_replay_limit(),
+        persistence: false,
+        rootkit_checks: false,
+        containers: Default::default(),
+        brute_force: Default::default(),
+        login_allowlist: vec![],
+        geoip_country_database: None,
+        geoip_asn_database: None,
+        reverse_dns_timeout: Config::default_reverse_dns_timeout(),
+        system_interval: Config::default_system_interval(),
+        network_interval: Config::default_network_interval(),
+        rules: vec![],
     }
 }
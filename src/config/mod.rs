@@ -2,10 +2,15 @@ use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 use std::{env, process};
 use walkdir::DirEntry;
+
+use crate::brute_force::BruteForceConfig;
+use crate::containers::ContainersConfig;
+use crate::rules::LogRule;
+use ipnet::IpNet;
 mod default;
 mod macos;
 mod windows;
-use eyre::{Context, Result};
+use eyre::{ensure, Context, Result};
 
 /// Snitch configurations
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
@@ -14,12 +19,62 @@ pub struct Config {
     pub sender: chatterbox::dispatcher::Sender,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub authentication_logs: Option<PathBuf>,
+    /// Linux audit log followed by `--watch-audit`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audit_log: Option<PathBuf>,
+    /// Read authentication events from the systemd journal instead of `authentication_logs`.
+    #[serde(default)]
+    pub authentication_journal: bool,
     #[serde(default = "Config::default_root")]
     pub snitch_root: String,
     #[serde(default = "Config::default_url")]
     pub url: String,
     #[serde(default = "Config::default_token")]
     pub token: String,
+    /// Use fanotify on Linux to report which process modified a file. Requires `CAP_SYS_ADMIN`.
+    #[serde(default)]
+    pub fanotify: bool,
+    /// Interval in seconds in which directories exceeding the file watch limit are polled.
+    #[serde(default = "Config::default_poll_interval")]
+    pub poll_interval: u64,
+    /// Maximum number of bytes of authentication logs replayed after a restart.
+    #[serde(default = "Config::default_log_replay_limit")]
+    pub log_replay_limit: u64,
+    /// Watch autostart locations like crontabs, systemd units and shell startup files and report
+    /// changes of them with high severity.
+    #[serde(default)]
+    pub persistence: bool,
+    /// Compare directory walks with `getdents64` and link counts and look for processes missing
+    /// from `/proc` to detect rootkits (Linux).
+    #[serde(default)]
+    pub rootkit_checks: bool,
+    /// Baseline the root file systems of Docker containers separately from the host.
+    #[serde(default)]
+    pub containers: ContainersConfig,
+    /// Thresholds of failed authentications reported as brute force attempts.
+    #[serde(default)]
+    pub brute_force: BruteForceConfig,
+    /// Networks logins from which are expected and reported at info level.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub login_allowlist: Vec<IpNet>,
+    /// MaxMind format database used to show the country of login sources.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub geoip_country_database: Option<PathBuf>,
+    /// MaxMind format database used to show the autonomous system of login sources.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub geoip_asn_database: Option<PathBuf>,
+    /// Timeout of reverse DNS lookups of login sources in milliseconds. `0` disables them.
+    #[serde(default = "Config::default_reverse_dns_timeout")]
+    pub reverse_dns_timeout: u64,
+    /// Interval in seconds in which `--watch-system` checks kernel modules and boot files.
+    #[serde(default = "Config::default_system_interval")]
+    pub system_interval: u64,
+    /// Interval in seconds in which `--watch-listeners` checks for new network listeners.
+    #[serde(default = "Config::default_network_interval")]
+    pub network_interval: u64,
+    /// Custom rules evaluated on log files.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<LogRule>,
 }
 
 impl Config {
@@ -36,6 +91,43 @@ impl Config {
     pub(crate) fn default_root() -> String {
         "/etc/snitch/".to_string()
     }
+
+    pub(crate) fn default_poll_interval() -> u64 {
+        300
+    }
+
+    pub(crate) fn default_log_replay_limit() -> u64 {
+        1_000_000
+    }
+
+    pub(crate) fn default_reverse_dns_timeout() -> u64 {
+        2000
+    }
+
+    pub(crate) fn default_system_interval() -> u64 {
+        60
+    }
+
+    pub(crate) fn default_network_interval() -> u64 {
+        30
+    }
+
+    /// Reject values which are valid YAML but cannot be used.
+    fn validate(&self) -> Result<()> {
+        ensure!(
+            self.poll_interval > 0,
+            "poll_interval must be at least 1 second"
+        );
+        ensure!(
+            self.system_interval > 0,
+            "system_interval must be at least 1 second"
+        );
+        ensure!(
+            self.network_interval > 0,
+            "network_interval must be at least 1 second"
+        );
+        Ok(())
+    }
 }
 
 impl Config {
@@ -45,6 +137,27 @@ impl Config {
         database_path
     }
 
+    /// Path of the database holding the state of watchers, e.g. log offsets. Unlike the hash
+    /// database it is not cleared on initialization.
+    pub fn state_database_path(&self) -> PathBuf {
+        let database_path = Path::new(&self.snitch_root).join(Path::new("state"));
+        assert!(database_path.is_absolute());
+        database_path
+    }
+
+    /// Path of the database a long running watcher keeps open, e.g. for the offsets of the logs
+    /// it follows. Each watcher has its own, since only one process can open a database.
+    pub fn watcher_database_path(&self, watcher: &str) -> PathBuf {
+        let database_path = Path::new(&self.snitch_root).join(format!("state-{watcher}"));
+        assert!(database_path.is_absolute());
+        database_path
+    }
+
+    /// Path of the file listing the open ssh sessions.
+    pub fn sessions_path(&self) -> PathBuf {
+        Path::new(&self.snitch_root).join("sessions.json")
+    }
+
     pub fn clear_database(&self) {
         if self.database_path().exists() {
             info!(
@@ -66,13 +179,16 @@ impl Config {
         paths
     }
 
-    /// Filters excluded paths such as the database path of snitch
+    /// Filters excluded paths such as the database paths of snitch and, in container mode, the
+    /// storage of Docker
     pub fn is_excluded_directory(&self, directory: &DirEntry) -> bool {
-        directory
+        let parent = directory
             .path()
             .parent()
-            .expect("failed getting parent directory")
-            == self.database_path()
+            .expect("failed getting parent directory");
+        parent == self.database_path()
+            || parent == self.state_database_path()
+            || self.containers.is_container_storage(directory.path())
     }
 
     /// get a basic configuration for demonstration. On Ubuntu and Debian this should be a good starting point.
@@ -102,7 +218,8 @@ pub fn load_config_from_file(path: &Path) -> Result<Config> {
     let reader = std::fs::File::open(path)
         .wrap_err(format!("Failed opening config file {:?}", path.to_owned()))?;
 
-    let config = serde_yaml::from_reader(reader)?;
+    let config: Config = serde_yaml::from_reader(reader)?;
+    config.validate()?;
 
     Ok(config)
 }
@@ -121,4 +238,18 @@ mod tests {
     fn test_basic_config() {
         let _x = Config::demo_config();
     }
+
+    #[test]
+    fn test_validate() {
+        let mut config = Config::demo_config();
+        assert!(config.validate().is_ok());
+        config.network_interval = 0;
+        assert!(config.validate().is_err());
+        config.network_interval = 30;
+        config.system_interval = 0;
+        assert!(config.validate().is_err());
+        config.system_interval = 60;
+        config.poll_interval = 0;
+        assert!(config.validate().is_err());
+    }
 }
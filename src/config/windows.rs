@@ -5,9 +5,25 @@ pub fn get_config() -> Config {
     Config {
         directories: vec!["C:/Windows".to_owned()],
         authentication_logs: None,
+        audit_log: None,
+        authentication_journal: false,
         sender: Sender::example(),
         snitch_root: "C:/ProgramData/snitch".to_owned(),
         url: Config::default_url(),
         token: "".to_string(),
+        fanotify: false,
+        poll_interval: Config::default_poll_interval(),
+        log_replay_limit: Config::default_log_replay_limit(),
+        persistence: false,
+        rootkit_checks: false,
+        containers: Default::default(),
+        brute_force: Default::default(),
+        login_allowlist: vec![],
+        geoip_country_database: None,
+        geoip_asn_database: None,
+        reverse_dns_timeout: Config::default_reverse_dns_timeout(),
+        system_interval: Config::default_system_interval(),
+        network_interval: Config::default_network_interval(),
+        rules: vec![],
     }
 }
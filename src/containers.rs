@@ -0,0 +1,374 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sled::{Db, Tree};
+use thiserror::Error;
+use walkdir::WalkDir;
+
+use crate::dispatcher::{MessageBackend, Severity};
+use crate::hashing::hash_file;
+use crate::persistence::ChangeKind;
+
+/// Tree of the hash database holding the containers of the baseline by id.
+static CONTAINERS_TREE: &str = "containers";
+/// Prefix of the trees holding the file hashes of a container.
+static CONTAINER_TREE_PREFIX: &str = "container:";
+
+static DEFAULT_DOCKER_ROOT: &str = "/var/lib/docker";
+/// Paths inside containers which are expected to change.
+static DEFAULT_IGNORE: [&str; 5] = ["/tmp", "/var/tmp", "/var/log", "/var/cache", "/run"];
+
+#[derive(Debug, Error)]
+pub enum ContainersError {
+    #[error(transparent)]
+    Sled(#[from] sled::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// Baseline the root file systems of running Docker containers separately from the host.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct ContainersConfig {
+    pub enabled: bool,
+    /// Data directory of Docker. It is excluded from the files of the host.
+    #[serde(default = "ContainersConfig::default_docker_root")]
+    pub docker_root: PathBuf,
+    /// Paths inside the containers which are not hashed, e.g. writable directories.
+    #[serde(default = "ContainersConfig::default_ignore")]
+    pub ignore: Vec<PathBuf>,
+}
+
+impl ContainersConfig {
+    fn default_docker_root() -> PathBuf {
+        PathBuf::from(DEFAULT_DOCKER_ROOT)
+    }
+
+    fn default_ignore() -> Vec<PathBuf> {
+        DEFAULT_IGNORE.iter().map(PathBuf::from).collect()
+    }
+
+    /// Returns `true` if container mode is enabled and `path` belongs to the storage of Docker.
+    pub fn is_container_storage(&self, path: &Path) -> bool {
+        self.enabled && path.starts_with(&self.docker_root)
+    }
+}
+
+impl Default for ContainersConfig {
+    fn default() -> Self {
+        ContainersConfig {
+            enabled: false,
+            docker_root: ContainersConfig::default_docker_root(),
+            ignore: ContainersConfig::default_ignore(),
+        }
+    }
+}
+
+/// A container as recorded in the baseline.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Container {
+    pub id: String,
+    pub name: String,
+    /// Digest of the image the container was created from.
+    pub image: String,
+    #[serde(skip)]
+    pub root: PathBuf,
+}
+
+impl Container {
+    fn short_id(&self) -> &str {
+        &self.id[..self.id.len().min(12)]
+    }
+}
+
+impl fmt::Display for Container {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}, image {})",
+            self.name,
+            self.short_id(),
+            self.image
+        )
+    }
+}
+
+/// The parts of `config.v2.json` of a container we need.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ContainerConfig {
+    #[serde(rename = "ID")]
+    id: String,
+    name: String,
+    image: String,
+    driver: String,
+    state: ContainerState,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ContainerState {
+    running: bool,
+}
+
+/// Read a running container from its `config.v2.json`. Only the overlay2 storage driver is
+/// supported.
+fn read_container(docker_root: &Path, config_path: &Path) -> Option<Container> {
+    let contents = std::fs::read_to_string(config_path)
+        .inspect_err(|e| warn!("cannot read {config_path:?}: {e}"))
+        .ok()?;
+    let config: ContainerConfig = serde_json::from_str(&contents)
+        .inspect_err(|e| warn!("cannot parse {config_path:?}: {e}"))
+        .ok()?;
+    if !config.state.running {
+        return None;
+    }
+    if config.driver != "overlay2" {
+        warn!(
+            "container {} uses unsupported storage driver {}",
+            config.name, config.driver
+        );
+        return None;
+    }
+    let mount_id = std::fs::read_to_string(
+        docker_root
+            .join("image/overlay2/layerdb/mounts")
+            .join(&config.id)
+            .join("mount-id"),
+    )
+    .ok()?;
+    Some(Container {
+        root: docker_root
+            .join("overlay2")
+            .join(mount_id.trim())
+            .join("merged"),
+        name: config.name.trim_start_matches('/').to_string(),
+        id: config.id,
+        image: config.image,
+    })
+}
+
+/// The running containers found in `docker_root`.
+pub fn running_containers(config: &ContainersConfig) -> Vec<Container> {
+    let mut containers: Vec<Container> = std::fs::read_dir(config.docker_root.join("containers"))
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            read_container(&config.docker_root, &entry.path().join("config.v2.json"))
+        })
+        .collect();
+    containers.sort_by(|a, b| a.name.cmp(&b.name));
+    containers
+}
+
+/// Hash the files of the root file system of `container` by their path inside the container.
+pub async fn hash_container(
+    config: &ContainersConfig,
+    container: &Container,
+) -> BTreeMap<String, String> {
+    let mut hashes = BTreeMap::new();
+    let walker = WalkDir::new(&container.root)
+        .into_iter()
+        .filter_entry(|entry| {
+            let path = Path::new("/").join(entry.path().strip_prefix(&container.root).unwrap());
+            !config
+                .ignore
+                .iter()
+                .any(|ignored| path.starts_with(ignored))
+        });
+    for entry in walker.filter_map(Result::ok) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let path = Path::new("/").join(entry.path().strip_prefix(&container.root).unwrap());
+        match hash_file(entry.path()).await {
+            Ok(hash) => {
+                hashes.insert(path.to_string_lossy().into_owned(), hash);
+            }
+            Err(e) => warn!("{e} on {:?}. Skipping.", entry.path()),
+        }
+    }
+    hashes
+}
+
+fn files_tree(db: &Db, id: &str) -> Result<Tree, sled::Error> {
+    db.open_tree(format!("{CONTAINER_TREE_PREFIX}{id}"))
+}
+
+/// Record the files of all running containers in `db`.
+pub async fn record_baseline(db: &Db, config: &ContainersConfig) -> Result<(), ContainersError> {
+    let containers_tree = db.open_tree(CONTAINERS_TREE)?;
+    for container in running_containers(config) {
+        info!("hashing container {container}");
+        let tree = files_tree(db, &container.id)?;
+        tree.clear()?;
+        for (path, hash) in hash_container(config, &container).await {
+            tree.insert(path.as_bytes(), hash.as_bytes())?;
+        }
+        containers_tree.insert(container.id.as_bytes(), serde_json::to_vec(&container)?)?;
+    }
+    Ok(())
+}
+
+/// The baseline of `container`. A container recreated from the same image, e.g. by
+/// `docker compose up`, gets a new id and is compared with the baseline of its predecessor.
+fn find_baseline(db: &Db, container: &Container) -> Result<Option<String>, ContainersError> {
+    let containers_tree = db.open_tree(CONTAINERS_TREE)?;
+    if containers_tree.contains_key(container.id.as_bytes())? {
+        return Ok(Some(container.id.clone()));
+    }
+    for entry in containers_tree.iter() {
+        let (_, value) = entry?;
+        let recorded: Container = serde_json::from_slice(&value)?;
+        if recorded.name == container.name && recorded.image == container.image {
+            return Ok(Some(recorded.id));
+        }
+    }
+    Ok(None)
+}
+
+/// A change inside a container compared to the baseline.
+#[derive(Debug, PartialEq)]
+pub enum ContainerChange {
+    File {
+        container: Container,
+        path: String,
+        kind: ChangeKind,
+    },
+    /// A running container without baseline.
+    Unknown(Container),
+}
+
+impl From<&ContainerChange> for MessageBackend {
+    fn from(value: &ContainerChange) -> Self {
+        match value {
+            ContainerChange::File {
+                container,
+                path,
+                kind,
+            } => {
+                let verb = match kind {
+                    ChangeKind::Created => "created",
+                    ChangeKind::Modified => "modified",
+                    ChangeKind::Removed => "removed",
+                };
+                MessageBackend::new_now(
+                    format!("File {verb} in container {}", container.name),
+                    format!("<code>{path}</code>\ncontainer: {container}"),
+                )
+            }
+            ContainerChange::Unknown(container) => MessageBackend::new_now(
+                format!("Container {} not in baseline", container.name),
+                format!("{container}\nRun --init to record it."),
+            )
+            .with_severity(Severity::Info),
+        }
+    }
+}
+
+/// Paths which were created, modified or removed between `old` and `new`.
+pub fn diff(
+    old: &BTreeMap<String, String>,
+    new: &BTreeMap<String, String>,
+) -> Vec<(String, ChangeKind)> {
+    let mut changes: Vec<(String, ChangeKind)> = new
+        .iter()
+        .filter_map(|(path, hash)| match old.get(path) {
+            None => Some((path.clone(), ChangeKind::Created)),
+            Some(old) if old != hash => Some((path.clone(), ChangeKind::Modified)),
+            Some(_) => None,
+        })
+        .collect();
+    changes.extend(
+        old.keys()
+            .filter(|path| !new.contains_key(*path))
+            .map(|path| (path.clone(), ChangeKind::Removed)),
+    );
+    changes
+}
+
+/// Compare the files of the running containers with the baseline in `db`.
+pub async fn scan(
+    db: &Db,
+    config: &ContainersConfig,
+) -> Result<Vec<ContainerChange>, ContainersError> {
+    let mut changes = vec![];
+    for container in running_containers(config) {
+        let Some(id) = find_baseline(db, &container)? else {
+            changes.push(ContainerChange::Unknown(container));
+            continue;
+        };
+        let baseline = files_tree(db, &id)?
+            .iter()
+            .map(|entry| {
+                let (path, hash) = entry?;
+                Ok((
+                    String::from_utf8_lossy(&path).into_owned(),
+                    String::from_utf8_lossy(&hash).into_owned(),
+                ))
+            })
+            .collect::<Result<BTreeMap<String, String>, sled::Error>>()?;
+        let current = hash_container(config, &container).await;
+        changes.extend(diff(&baseline, &current).into_iter().map(|(path, kind)| {
+            ContainerChange::File {
+                container: container.clone(),
+                path,
+                kind,
+            }
+        }));
+    }
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> ContainersConfig {
+        ContainersConfig {
+            enabled: true,
+            docker_root: PathBuf::from("test/docker"),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hash_container() {
+        let config = config();
+        let containers = running_containers(&config);
+        assert_eq!(containers.len(), 1);
+        assert_eq!(containers[0].name, "web");
+        assert_eq!(
+            containers[0].to_string(),
+            "web (3f2a9c4e1b7d, image sha256:a8758716bb6aa4d90071160d27028fe4eaee7ce8166221a97d30440c8eac2be6)"
+        );
+        let hashes = hash_container(&config, &containers[0]).await;
+        assert_eq!(
+            hashes.keys().collect::<Vec<&String>>(),
+            vec!["/etc/passwd", "/usr/bin/entrypoint"]
+        );
+        assert!(config.is_container_storage(&containers[0].root));
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = BTreeMap::from([
+            ("/etc/passwd".to_string(), "A".to_string()),
+            ("/etc/shadow".to_string(), "B".to_string()),
+        ]);
+        let new = BTreeMap::from([
+            ("/etc/passwd".to_string(), "C".to_string()),
+            ("/usr/bin/kworker".to_string(), "D".to_string()),
+        ]);
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                ("/etc/passwd".to_string(), ChangeKind::Modified),
+                ("/usr/bin/kworker".to_string(), ChangeKind::Created),
+                ("/etc/shadow".to_string(), ChangeKind::Removed),
+            ]
+        );
+    }
+}
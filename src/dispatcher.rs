@@ -3,6 +3,7 @@ use chrono::{DateTime, Utc};
 use lazy_static::lazy_static;
 use reqwest::header::{HeaderMap, AUTHORIZATION, CONTENT_TYPE};
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use tokio::sync::broadcast::error::SendError as BroadcastSendError;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 
@@ -14,17 +15,45 @@ lazy_static! {
         .to_owned();
 }
 
+/// How urgent a message is. High and critical messages are marked in the title.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    #[default]
+    Warning,
+    High,
+    Critical,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self {
+            Severity::Info => "INFO",
+            Severity::Warning => "WARNING",
+            Severity::High => "HIGH",
+            Severity::Critical => "CRITICAL",
+        };
+        write!(f, "{severity}")
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct MessageBackend {
     pub hostname: String,
     pub title: String,
     pub body: String,
     pub timestamp: DateTime<Utc>,
+    #[serde(default)]
+    pub severity: Severity,
 }
 
 impl Notification for MessageBackend {
     fn message(&self) -> Message {
-        let title = self.title.clone();
+        let title = match self.severity >= Severity::High {
+            true => format!("[{}] {}", self.severity, self.title),
+            false => self.title.clone(),
+        };
         let body = format!("{}\n\n{}\n{}", self.body, *HOSTNAME, self.timestamp);
         Message { title, body }
     }
@@ -38,8 +67,22 @@ impl MessageBackend {
             title,
             body,
             timestamp,
+            severity: Severity::default(),
         }
     }
+
+    pub fn with_severity(mut self, severity: Severity) -> Self {
+        self.severity = severity;
+        self
+    }
+}
+
+/// Escape `text` so log lines, file names and other text controlled by attackers cannot inject
+/// markup into messages.
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Default)]
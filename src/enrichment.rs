@@ -0,0 +1,221 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+use tokio::task::{self, JoinSet};
+use tokio::time;
+
+use crate::config::Config;
+use crate::dispatcher::escape_html;
+
+/// Information about a source address from local GeoIP databases and reverse DNS.
+#[derive(Debug, Default, PartialEq)]
+pub struct Enrichment {
+    /// ISO code and english name of the country.
+    pub country: Option<(String, String)>,
+    /// Number and organization of the autonomous system.
+    pub asn: Option<(u32, String)>,
+    pub reverse_dns: Option<String>,
+}
+
+impl fmt::Display for Enrichment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some((iso_code, name)) = &self.country {
+            writeln!(f, "country: <b>{name}</b> ({iso_code})")?;
+        }
+        if let Some((number, organization)) = &self.asn {
+            writeln!(f, "ASN: <b>AS{number}</b> {organization}")?;
+        }
+        if let Some(reverse_dns) = &self.reverse_dns {
+            writeln!(f, "reverse DNS: <code>{}</code>", escape_html(reverse_dns))?;
+        }
+        Ok(())
+    }
+}
+
+/// Readers of the MaxMind format databases configured in `geoip_country_database` and
+/// `geoip_asn_database`. Lookups happen offline.
+#[derive(Default)]
+pub struct GeoIp {
+    country: Option<Reader<Vec<u8>>>,
+    asn: Option<Reader<Vec<u8>>>,
+}
+
+impl GeoIp {
+    pub fn from_config(config: &Config) -> Result<GeoIp, MaxMindDBError> {
+        let open = |path: Option<&Path>| path.map(Reader::open_readfile).transpose();
+        Ok(GeoIp {
+            country: open(config.geoip_country_database.as_deref())?,
+            asn: open(config.geoip_asn_database.as_deref())?,
+        })
+    }
+
+    /// Look up the country and autonomous system of `ip`. Addresses which are not in the
+    /// databases, e.g. private ones, yield `None`.
+    pub fn lookup(&self, ip: IpAddr, enrichment: &mut Enrichment) {
+        if let Some(reader) = &self.country {
+            enrichment.country = reader
+                .lookup::<geoip2::Country>(ip)
+                .ok()
+                .and_then(|record| record.country)
+                .map(|country| {
+                    let iso_code = country.iso_code.unwrap_or("??").to_string();
+                    let name = country
+                        .names
+                        .and_then(|names| names.get("en").map(|name| name.to_string()))
+                        .unwrap_or_else(|| iso_code.clone());
+                    (iso_code, name)
+                });
+        }
+        if let Some(reader) = &self.asn {
+            enrichment.asn = reader.lookup::<geoip2::Asn>(ip).ok().and_then(|record| {
+                let number = record.autonomous_system_number?;
+                let organization = record.autonomous_system_organization.unwrap_or_default();
+                Some((number, organization.to_string()))
+            });
+        }
+    }
+}
+
+/// Resolve the hostname of `ip`. Gives up after `timeout` since resolvers can block for a long
+/// time on unreachable name servers.
+pub async fn reverse_dns(ip: IpAddr, timeout: Duration) -> Option<String> {
+    lookup_with_timeout(ip, timeout, dns_lookup::lookup_addr).await
+}
+
+/// Run the blocking `lookup` of `ip` for at most `timeout`. Numeric results are dropped.
+async fn lookup_with_timeout(
+    ip: IpAddr,
+    timeout: Duration,
+    lookup: fn(&IpAddr) -> std::io::Result<String>,
+) -> Option<String> {
+    let lookup = task::spawn_blocking(move || lookup(&ip));
+    match time::timeout(timeout, lookup).await {
+        Ok(Ok(Ok(hostname))) if hostname.parse::<IpAddr>().is_err() => Some(hostname),
+        Ok(Ok(Ok(_))) => None,
+        Ok(Ok(Err(e))) => {
+            debug!("reverse lookup of {ip} failed: {e}");
+            None
+        }
+        Ok(Err(e)) => {
+            error!("reverse lookup of {ip} panicked: {e}");
+            None
+        }
+        Err(_) => {
+            debug!("reverse lookup of {ip} timed out");
+            None
+        }
+    }
+}
+
+/// Resolve the hostnames of `ips` concurrently, so that a batch of logins, e.g. replayed after
+/// a restart, waits at most `reverse_dns_timeout` milliseconds. Addresses without hostname are
+/// missing from the result.
+pub async fn resolve_hostnames(
+    config: &Config,
+    ips: impl IntoIterator<Item = IpAddr>,
+) -> HashMap<IpAddr, String> {
+    if config.reverse_dns_timeout == 0 {
+        return HashMap::new();
+    }
+    let timeout = Duration::from_millis(config.reverse_dns_timeout);
+    let mut lookups = JoinSet::new();
+    for ip in ips.into_iter().collect::<HashSet<IpAddr>>() {
+        lookups.spawn(async move { (ip, reverse_dns(ip, timeout).await) });
+    }
+    let mut hostnames = HashMap::new();
+    while let Some(result) = lookups.join_next().await {
+        if let Ok((ip, Some(hostname))) = result {
+            hostnames.insert(ip, hostname);
+        }
+    }
+    hostnames
+}
+
+/// Collect everything known about the source address `ip`. `hostnames` are the results of
+/// [`resolve_hostnames`].
+pub fn enrich(geoip: &GeoIp, ip: IpAddr, hostnames: &HashMap<IpAddr, String>) -> Enrichment {
+    let mut enrichment = Enrichment::default();
+    geoip.lookup(ip, &mut enrichment);
+    enrichment.reverse_dns = hostnames.get(&ip).cloned();
+    enrichment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let enrichment = Enrichment {
+            country: Some(("DE".to_string(), "Germany".to_string())),
+            asn: Some((64496, "Example Networks".to_string())),
+            reverse_dns: None,
+        };
+        assert_eq!(
+            enrichment.to_string(),
+            "country: <b>Germany</b> (DE)\nASN: <b>AS64496</b> Example Networks\n"
+        );
+        assert_eq!(Enrichment::default().to_string(), "");
+    }
+
+    #[test]
+    fn test_geoip() {
+        let mut config = Config::demo_config();
+        config.geoip_country_database = Some("test/geoip/country.mmdb".into());
+        config.geoip_asn_database = Some("test/geoip/asn.mmdb".into());
+        let geoip = GeoIp::from_config(&config).unwrap();
+        let hostnames = HashMap::from([(
+            "203.0.113.7".parse().unwrap(),
+            "scanner.example.com".to_string(),
+        )]);
+        assert_eq!(
+            enrich(&geoip, "203.0.113.7".parse().unwrap(), &hostnames),
+            Enrichment {
+                country: Some(("DE".to_string(), "Germany".to_string())),
+                asn: Some((64496, "Example Networks".to_string())),
+                reverse_dns: Some("scanner.example.com".to_string()),
+            }
+        );
+        // private addresses are not in the databases
+        assert_eq!(
+            enrich(&geoip, "10.0.0.1".parse().unwrap(), &hostnames),
+            Enrichment::default()
+        );
+        assert_eq!(
+            enrich(
+                &GeoIp::default(),
+                "203.0.113.7".parse().unwrap(),
+                &HashMap::new()
+            ),
+            Enrichment::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reverse_dns() {
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        let timeout = Duration::from_millis(100);
+        let found = lookup_with_timeout(ip, timeout, |_| Ok("host.example.com".to_string())).await;
+        assert_eq!(found.as_deref(), Some("host.example.com"));
+        // resolvers return the address itself if there is no PTR record
+        assert_eq!(
+            lookup_with_timeout(ip, timeout, |ip| Ok(ip.to_string())).await,
+            None
+        );
+        let not_found = |_: &IpAddr| Err(std::io::Error::other("no PTR record"));
+        assert_eq!(lookup_with_timeout(ip, timeout, not_found).await, None);
+        let unreachable = |_: &IpAddr| {
+            std::thread::sleep(Duration::from_millis(500));
+            Ok("late.example.com".to_string())
+        };
+        assert_eq!(lookup_with_timeout(ip, timeout, unreachable).await, None);
+
+        let mut config = Config::demo_config();
+        config.reverse_dns_timeout = 0;
+        assert!(resolve_hostnames(&config, [ip]).await.is_empty());
+    }
+}
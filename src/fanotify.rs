@@ -0,0 +1,222 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use crate::dispatcher::{escape_html, MessageBackend};
+
+/// Size of the buffer events are read into. Large enough to hold a batch of events.
+const BUFFER_SIZE: usize = 8192;
+
+/// The process that caused a fanotify event. Fields are `None` if the process exited before
+/// they could be read from `/proc`.
+#[derive(Debug, Clone)]
+pub struct ProcessInfo {
+    pub pid: i32,
+    pub exe: Option<PathBuf>,
+    pub uid: Option<u32>,
+    pub cmdline: Option<String>,
+}
+
+impl ProcessInfo {
+    /// Collect information about process `pid` from `/proc`.
+    pub fn from_pid(pid: i32) -> ProcessInfo {
+        let proc_dir = Path::new("/proc").join(pid.to_string());
+        let exe = fs::read_link(proc_dir.join("exe")).ok();
+        let uid = fs::read_to_string(proc_dir.join("status"))
+            .ok()
+            .and_then(|status| parse_uid(&status));
+        let cmdline = fs::read(proc_dir.join("cmdline"))
+            .ok()
+            .filter(|cmdline| !cmdline.is_empty())
+            .map(|cmdline| parse_cmdline(&cmdline));
+        ProcessInfo {
+            pid,
+            exe,
+            uid,
+            cmdline,
+        }
+    }
+}
+
+impl fmt::Display for ProcessInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let unknown = "unknown".to_string();
+        write!(
+            f,
+            "pid: {}\nuid: {}\nexe: {}\ncmdline: {}",
+            self.pid,
+            self.uid
+                .map(|uid| uid.to_string())
+                .unwrap_or(unknown.clone()),
+            self.exe
+                .as_ref()
+                .map(|exe| escape_html(&exe.to_string_lossy()))
+                .unwrap_or(unknown.clone()),
+            self.cmdline
+                .as_deref()
+                .map(escape_html)
+                .unwrap_or(unknown.clone()),
+        )
+    }
+}
+
+/// Extract the real uid from the contents of `/proc/<pid>/status`.
+fn parse_uid(status: &str) -> Option<u32> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Uid:"))
+        .and_then(|uids| uids.split_whitespace().next())
+        .and_then(|uid| uid.parse().ok())
+}
+
+/// `/proc/<pid>/cmdline` separates arguments by null bytes.
+fn parse_cmdline(cmdline: &[u8]) -> String {
+    cmdline
+        .split(|byte| *byte == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(String::from_utf8_lossy)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A file modification reported by fanotify together with the process that caused it.
+#[derive(Debug)]
+pub struct FanotifyEvent {
+    pub path: PathBuf,
+    pub process: ProcessInfo,
+}
+
+impl From<FanotifyEvent> for MessageBackend {
+    fn from(event: FanotifyEvent) -> Self {
+        Self::new_now(
+            "modified".to_string(),
+            format!("{}\n\n{}", event.path.display(), event.process),
+        )
+    }
+}
+
+/// Events read from a fanotify group.
+pub struct FanotifyEvents {
+    pub events: Vec<FanotifyEvent>,
+    /// The kernel event queue overflowed and events were lost.
+    pub overflow: bool,
+}
+
+/// A fanotify group reporting closed-after-write files on the marked mounts.
+pub struct Fanotify {
+    fd: OwnedFd,
+}
+
+impl Fanotify {
+    /// Initialize a fanotify group. Fails with `EPERM` if snitch lacks `CAP_SYS_ADMIN`.
+    pub fn new() -> io::Result<Fanotify> {
+        let fd = unsafe {
+            libc::fanotify_init(
+                libc::FAN_CLASS_NOTIF | libc::FAN_CLOEXEC,
+                (libc::O_RDONLY | libc::O_LARGEFILE) as libc::c_uint,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Fanotify {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        })
+    }
+
+    /// Mark the mount containing `path`. Events are reported for the whole mount and need to
+    /// be filtered by the caller.
+    pub fn mark(&self, path: &Path) -> io::Result<()> {
+        let mut c_path = path.as_os_str().as_bytes().to_vec();
+        c_path.push(0);
+        let result = unsafe {
+            libc::fanotify_mark(
+                self.fd.as_raw_fd(),
+                libc::FAN_MARK_ADD | libc::FAN_MARK_MOUNT,
+                libc::FAN_CLOSE_WRITE,
+                libc::AT_FDCWD,
+                c_path.as_ptr() as *const libc::c_char,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Block until events are available and return them.
+    pub fn read_events(&self) -> io::Result<FanotifyEvents> {
+        let mut buffer = vec![0u8; BUFFER_SIZE];
+        let count = unsafe {
+            libc::read(
+                self.fd.as_raw_fd(),
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+            )
+        };
+        if count < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let metadata_size = std::mem::size_of::<libc::fanotify_event_metadata>();
+        let mut events = vec![];
+        let mut overflow = false;
+        let mut offset = 0;
+        while offset + metadata_size <= count as usize {
+            let metadata: libc::fanotify_event_metadata = unsafe {
+                std::ptr::read_unaligned(
+                    buffer[offset..].as_ptr() as *const libc::fanotify_event_metadata
+                )
+            };
+            if metadata.vers != libc::FANOTIFY_METADATA_VERSION {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "unsupported fanotify metadata version",
+                ));
+            }
+            if metadata.event_len == 0 {
+                break;
+            }
+            offset += metadata.event_len as usize;
+
+            if metadata.mask & libc::FAN_Q_OVERFLOW != 0 || metadata.fd < 0 {
+                overflow = true;
+                continue;
+            }
+            let fd = unsafe { OwnedFd::from_raw_fd(metadata.fd) };
+            let path = match fs::read_link(format!("/proc/self/fd/{}", fd.as_raw_fd())) {
+                Ok(path) => path,
+                Err(err) => {
+                    warn!("failed resolving path of fanotify event: {err}");
+                    continue;
+                }
+            };
+            events.push(FanotifyEvent {
+                path,
+                process: ProcessInfo::from_pid(metadata.pid),
+            });
+        }
+        Ok(FanotifyEvents { events, overflow })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process_info_self() {
+        let process = ProcessInfo::from_pid(std::process::id() as i32);
+        assert!(process.exe.is_some());
+        assert!(process.uid.is_some());
+        assert!(process.cmdline.is_some());
+    }
+
+    #[test]
+    fn test_parse_cmdline() {
+        assert_eq!(parse_cmdline(b"vim\0/etc/passwd\0"), "vim /etc/passwd");
+    }
+}
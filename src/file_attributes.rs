@@ -0,0 +1,444 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use sled::Db;
+use thiserror::Error;
+use walkdir::WalkDir;
+
+use crate::config::Config;
+use crate::dispatcher::{MessageBackend, Severity};
+
+/// Tree of the hash database holding the extended attributes and flags of the baseline. Only
+/// files with any of them are stored.
+static FILE_ATTRIBUTES_TREE: &str = "file_attributes";
+
+#[derive(Debug, Error)]
+pub enum FileAttributesError {
+    #[error(transparent)]
+    Sled(#[from] sled::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// Names of the Linux capabilities by bit number.
+static CAPABILITIES: [&str; 41] = [
+    "chown",
+    "dac_override",
+    "dac_read_search",
+    "fowner",
+    "fsetid",
+    "kill",
+    "setgid",
+    "setuid",
+    "setpcap",
+    "linux_immutable",
+    "net_bind_service",
+    "net_broadcast",
+    "net_admin",
+    "net_raw",
+    "ipc_lock",
+    "ipc_owner",
+    "sys_module",
+    "sys_rawio",
+    "sys_chroot",
+    "sys_ptrace",
+    "sys_pacct",
+    "sys_admin",
+    "sys_boot",
+    "sys_nice",
+    "sys_resource",
+    "sys_time",
+    "sys_tty_config",
+    "mknod",
+    "lease",
+    "audit_write",
+    "audit_control",
+    "setfcap",
+    "mac_override",
+    "mac_admin",
+    "syslog",
+    "wake_alarm",
+    "block_suspend",
+    "audit_read",
+    "perfmon",
+    "bpf",
+    "checkpoint_restore",
+];
+
+/// Extended attributes and inode flags which change what a file may do without changing its
+/// contents.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct FileAttributes {
+    /// File capabilities (`security.capability`) formatted like `getcap` does.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<String>,
+    /// SELinux label (`security.selinux`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selinux: Option<String>,
+    /// Access ACL (`system.posix_acl_access`) formatted like `getfacl` does.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acl: Option<String>,
+    /// `chattr +i`
+    #[serde(default)]
+    pub immutable: bool,
+    /// `chattr +a`
+    #[serde(default)]
+    pub append_only: bool,
+}
+
+impl FileAttributes {
+    pub fn is_empty(&self) -> bool {
+        *self == FileAttributes::default()
+    }
+
+    /// Read the attributes of the file at `path`.
+    #[cfg(target_os = "linux")]
+    pub fn read(path: &Path) -> FileAttributes {
+        let flags = linux::inode_flags(path).unwrap_or_default();
+        FileAttributes {
+            capabilities: linux::xattr(path, "security.capability")
+                .and_then(|value| decode_capabilities(&value)),
+            selinux: linux::xattr(path, "security.selinux").map(|value| {
+                String::from_utf8_lossy(&value)
+                    .trim_end_matches('\0')
+                    .to_string()
+            }),
+            acl: linux::xattr(path, "system.posix_acl_access").and_then(|value| decode_acl(&value)),
+            immutable: flags & linux::FS_IMMUTABLE_FL != 0,
+            append_only: flags & linux::FS_APPEND_FL != 0,
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn read(_path: &Path) -> FileAttributes {
+        FileAttributes::default()
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::ffi::CString;
+    use std::fs::OpenOptions;
+    use std::os::fd::AsRawFd;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::path::Path;
+
+    pub const FS_IMMUTABLE_FL: libc::c_int = 0x10;
+    pub const FS_APPEND_FL: libc::c_int = 0x20;
+
+    /// Read the extended attribute `name` of `path` without following symbolic links.
+    pub fn xattr(path: &Path, name: &str) -> Option<Vec<u8>> {
+        let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+        let name = CString::new(name).ok()?;
+        // SAFETY: both strings are NUL terminated, a null buffer of size 0 queries the size
+        let size =
+            unsafe { libc::lgetxattr(path.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0) };
+        if size <= 0 {
+            return None;
+        }
+        let mut value = vec![0u8; size as usize];
+        // SAFETY: `value` has room for `size` bytes
+        let size = unsafe {
+            libc::lgetxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_mut_ptr() as *mut libc::c_void,
+                value.len(),
+            )
+        };
+        if size < 0 {
+            return None;
+        }
+        value.truncate(size as usize);
+        Some(value)
+    }
+
+    /// Read the inode flags set by `chattr`.
+    pub fn inode_flags(path: &Path) -> Option<libc::c_int> {
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK | libc::O_NOFOLLOW | libc::O_NOCTTY)
+            .open(path)
+            .ok()?;
+        let mut flags: libc::c_int = 0;
+        // SAFETY: the descriptor is valid while `file` lives, the kernel writes an int
+        let result = unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_GETFLAGS, &mut flags) };
+        (result == 0).then_some(flags)
+    }
+}
+
+fn le_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn capability_names(bits: u64) -> String {
+    (0..64)
+        .filter(|bit| bits & (1 << bit) != 0)
+        .map(|bit| match CAPABILITIES.get(bit) {
+            Some(name) => format!("cap_{name}"),
+            None => format!("cap_{bit}"),
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Decode a `security.capability` value (`struct vfs_cap_data`).
+fn decode_capabilities(value: &[u8]) -> Option<String> {
+    const VFS_CAP_FLAGS_EFFECTIVE: u32 = 0x000001;
+    let magic = le_u32(value, 0)?;
+    let permitted = le_u32(value, 4)? as u64 | (le_u32(value, 12).unwrap_or(0) as u64) << 32;
+    let inheritable = le_u32(value, 8)? as u64 | (le_u32(value, 16).unwrap_or(0) as u64) << 32;
+    let mut parts = vec![];
+    if permitted != 0 {
+        let effective = match magic & VFS_CAP_FLAGS_EFFECTIVE != 0 {
+            true => "e",
+            false => "",
+        };
+        parts.push(format!("{}+{effective}p", capability_names(permitted)));
+    }
+    if inheritable != 0 {
+        parts.push(format!("{}+i", capability_names(inheritable)));
+    }
+    Some(parts.join(" "))
+}
+
+/// Decode a `system.posix_acl_access` value: a version followed by (tag, permissions, id)
+/// entries.
+fn decode_acl(value: &[u8]) -> Option<String> {
+    let entries = value.get(4..)?.chunks_exact(8).map(|entry| {
+        let tag = u16::from_le_bytes([entry[0], entry[1]]);
+        let permissions = u16::from_le_bytes([entry[2], entry[3]]);
+        let id = u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]);
+        let qualifier = match tag {
+            0x01 => "user:".to_string(),
+            0x02 => format!("user:{id}"),
+            0x04 => "group:".to_string(),
+            0x08 => format!("group:{id}"),
+            0x10 => "mask:".to_string(),
+            _ => "other:".to_string(),
+        };
+        let bit = |mask, flag| match permissions & mask != 0 {
+            true => flag,
+            false => '-',
+        };
+        format!("{qualifier}:{}{}{}", bit(4, 'r'), bit(2, 'w'), bit(1, 'x'))
+    });
+    Some(entries.collect::<Vec<String>>().join(","))
+}
+
+/// Attributes of the files below the configured directories which have any.
+pub fn collect(config: &Config) -> BTreeMap<String, FileAttributes> {
+    let mut attributes = BTreeMap::new();
+    for directory in config.directories() {
+        let walker = WalkDir::new(directory)
+            .into_iter()
+            .filter_entry(|e| !config.is_excluded_directory(e));
+        for entry in walker.filter_map(Result::ok) {
+            if entry.path_is_symlink()
+                || !(entry.file_type().is_file() || entry.file_type().is_dir())
+            {
+                continue;
+            }
+            let file_attributes = FileAttributes::read(entry.path());
+            if !file_attributes.is_empty() {
+                attributes.insert(entry.path().to_string_lossy().into_owned(), file_attributes);
+            }
+        }
+    }
+    attributes
+}
+
+/// Replace the attributes of the baseline in `db`.
+pub fn record(
+    db: &Db,
+    attributes: &BTreeMap<String, FileAttributes>,
+) -> Result<(), FileAttributesError> {
+    let tree = db.open_tree(FILE_ATTRIBUTES_TREE)?;
+    tree.clear()?;
+    for (path, file_attributes) in attributes {
+        tree.insert(path.as_bytes(), serde_json::to_vec(file_attributes)?)?;
+    }
+    Ok(())
+}
+
+/// Read the attributes of the baseline from `db`.
+pub fn load(db: &Db) -> Result<BTreeMap<String, FileAttributes>, FileAttributesError> {
+    db.open_tree(FILE_ATTRIBUTES_TREE)?
+        .iter()
+        .map(|entry| {
+            let (path, file_attributes) = entry?;
+            Ok((
+                String::from_utf8_lossy(&path).into_owned(),
+                serde_json::from_slice(&file_attributes)?,
+            ))
+        })
+        .collect()
+}
+
+/// Changed attributes of a file.
+#[derive(Debug, PartialEq)]
+pub struct AttributeChange {
+    pub path: String,
+    pub old: FileAttributes,
+    pub new: FileAttributes,
+}
+
+impl AttributeChange {
+    pub fn severity(&self) -> Severity {
+        if self.new.capabilities.is_some() && self.old.capabilities != self.new.capabilities {
+            Severity::Critical
+        } else if self.old.immutable != self.new.immutable
+            || self.old.append_only != self.new.append_only
+            || self.old.acl != self.new.acl
+        {
+            Severity::High
+        } else {
+            Severity::Warning
+        }
+    }
+}
+
+impl fmt::Display for AttributeChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let describe = |value: &Option<String>| value.clone().unwrap_or("none".to_string());
+        let mut changes = vec![];
+        for (name, old, new) in [
+            (
+                "capabilities",
+                &self.old.capabilities,
+                &self.new.capabilities,
+            ),
+            ("SELinux label", &self.old.selinux, &self.new.selinux),
+            ("ACL", &self.old.acl, &self.new.acl),
+        ] {
+            if old != new {
+                changes.push(format!(
+                    "{name} <code>{}</code> → <code>{}</code>",
+                    describe(old),
+                    describe(new)
+                ));
+            }
+        }
+        for (name, old, new) in [
+            ("immutable", self.old.immutable, self.new.immutable),
+            ("append only", self.old.append_only, self.new.append_only),
+        ] {
+            if old != new {
+                changes.push(format!("{name} {old} → {new}"));
+            }
+        }
+        write!(f, "<code>{}</code>: {}", self.path, changes.join(", "))
+    }
+}
+
+impl From<&AttributeChange> for MessageBackend {
+    fn from(value: &AttributeChange) -> Self {
+        MessageBackend::new_now("Metadata changed".to_string(), value.to_string())
+            .with_severity(value.severity())
+    }
+}
+
+/// Compare the attributes of the baseline with the current ones. Files without attributes in the
+/// baseline are only reported if they gained more than an SELinux label, as every new file gets
+/// one on SELinux systems.
+pub fn diff(
+    old: &BTreeMap<String, FileAttributes>,
+    new: &BTreeMap<String, FileAttributes>,
+) -> Vec<AttributeChange> {
+    let mut changes = vec![];
+    for (path, new_attributes) in new {
+        let old_attributes = old.get(path).cloned().unwrap_or(FileAttributes {
+            selinux: new_attributes.selinux.clone(),
+            ..Default::default()
+        });
+        if old_attributes != *new_attributes {
+            changes.push(AttributeChange {
+                path: path.clone(),
+                old: old_attributes,
+                new: new_attributes.clone(),
+            });
+        }
+    }
+    for (path, old_attributes) in old {
+        // removed files are reported by the hash comparison
+        if !new.contains_key(path) && Path::new(path).exists() {
+            changes.push(AttributeChange {
+                path: path.clone(),
+                old: old_attributes.clone(),
+                new: FileAttributes::default(),
+            });
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        // setcap cap_setuid,cap_net_raw+ep
+        let capability = [
+            0x01, 0x00, 0x00, 0x02, 0x80, 0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(
+            decode_capabilities(&capability).unwrap(),
+            "cap_setuid,cap_net_raw+ep"
+        );
+
+        // setfacl -m u:1000:rw
+        let mut acl = vec![0x02, 0x00, 0x00, 0x00];
+        for (tag, permissions, id) in [
+            (0x01u16, 6u16, u32::MAX),
+            (0x02, 6, 1000),
+            (0x04, 4, u32::MAX),
+            (0x10, 6, u32::MAX),
+            (0x20, 4, u32::MAX),
+        ] {
+            acl.extend(tag.to_le_bytes());
+            acl.extend(permissions.to_le_bytes());
+            acl.extend(id.to_le_bytes());
+        }
+        assert_eq!(
+            decode_acl(&acl).unwrap(),
+            "user::rw-,user:1000:rw-,group::r--,mask::rw-,other::r--"
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let path = "/usr/bin/python3".to_string();
+        let old = BTreeMap::new();
+        let new = BTreeMap::from([(
+            path.clone(),
+            FileAttributes {
+                capabilities: Some("cap_setuid+ep".to_string()),
+                ..Default::default()
+            },
+        )]);
+        let changes = diff(&old, &new);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].severity(), Severity::Critical);
+        assert_eq!(
+            changes[0].to_string(),
+            "<code>/usr/bin/python3</code>: capabilities <code>none</code> → <code>cap_setuid+ep</code>"
+        );
+
+        // new files only carrying an SELinux label are not reported
+        let labelled = BTreeMap::from([(
+            path,
+            FileAttributes {
+                selinux: Some("system_u:object_r:bin_t:s0".to_string()),
+                ..Default::default()
+            },
+        )]);
+        assert!(diff(&old, &labelled).is_empty());
+    }
+}
@@ -1,19 +1,35 @@
 use data_encoding::HEXUPPER;
-use notify::{Event, EventKind, RecursiveMode, Watcher};
+use notify::event::{CreateKind, DataChange, ModifyKind, RemoveKind};
+use notify::{Event, EventKind, PollWatcher, RecursiveMode, Watcher};
 use ring::digest::{Context, Digest, SHA256};
 use sled::Db;
+use std::collections::HashSet;
 use std::fs::File;
 use std::io::{BufReader, Read};
-use std::path::Path;
-use std::sync::mpsc::channel;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Sender};
+use std::time::Duration;
 use thiserror::Error;
 use walkdir::WalkDir;
 
 extern crate notify;
+use crate::accounts::{self, AccountChange, AccountsError};
+use crate::authentication_logs;
+use crate::authorized_keys::{self, AuthorizedKeysError};
 use crate::config::Config;
+use crate::containers::{self, ContainersError};
 use crate::dispatcher::{MessageBackend, SnitchDispatcher};
+#[cfg(target_os = "linux")]
+use crate::fanotify::{Fanotify, FanotifyEvent};
+use crate::file_attributes::{self, FileAttributesError};
+use crate::network::{self, NetworkError};
+use crate::permissions::{self, PermissionsError};
 use crate::persist::{open_database, upsert_hashes, PersistError};
+use crate::persistence::{self, ChangeKind, Mechanism, PersistenceChange};
+#[cfg(target_os = "linux")]
+use crate::rootkit;
 use crate::style::get_progressbar;
+use crate::system::{self, SystemError};
 
 /// Calculate a `SHA256` hash from `reader`.
 async fn sha256_digest<R: Read>(mut reader: R) -> std::io::Result<Digest> {
@@ -49,6 +65,22 @@ pub enum HashDBError {
     IO(#[from] std::io::Error),
     #[error(transparent)]
     Persist(#[from] PersistError),
+    #[error(transparent)]
+    Accounts(#[from] AccountsError),
+    #[error(transparent)]
+    AuthorizedKeys(#[from] AuthorizedKeysError),
+    #[error(transparent)]
+    Permissions(#[from] PermissionsError),
+    #[error(transparent)]
+    FileAttributes(#[from] FileAttributesError),
+    #[error(transparent)]
+    System(#[from] SystemError),
+    #[error(transparent)]
+    Network(#[from] NetworkError),
+    #[error(transparent)]
+    Containers(#[from] ContainersError),
+    #[error(transparent)]
+    Notify(#[from] notify::Error),
 }
 
 /// Initialize the file hash database
@@ -65,6 +97,27 @@ pub async fn init_hash_db(
         progressbar.set_message(format!("{}", directory.display()));
         upsert_hash_tree(&db, config, dispatcher, directory).await?;
     }
+    accounts::record_snapshots(&db, &config.directories())?;
+    authorized_keys::record_baseline(config)?;
+    permissions::record(&db, &permissions::collect(config))?;
+    file_attributes::record(&db, &file_attributes::collect(config))?;
+    system::record_baseline(config)?;
+    network::record_baseline(config)?;
+    match authentication_logs::seed_known_logins(config).await {
+        Ok(count) => info!("remembered {count} logins from the authentication logs"),
+        Err(e) => warn!("failed remembering logins from the authentication logs: {e}"),
+    }
+    if config.containers.enabled {
+        containers::record_baseline(&db, &config.containers).await?;
+    }
+    if config.persistence {
+        for location in persistence::locations() {
+            if location.exists() && !is_in_directories(config, &location) {
+                upsert_hash_tree(&db, config, dispatcher, &location).await?;
+            }
+            persistence::record_links(&db, &location)?;
+        }
+    }
     progressbar.finish_with_message(format!("database checksum: {}", db.checksum()?));
 
     Ok(())
@@ -86,6 +139,8 @@ async fn upsert_hash_tree(
     let walker = WalkDir::new(start_path)
         .into_iter()
         .filter_entry(|e| !config.is_excluded_directory(e));
+    #[cfg(target_os = "linux")]
+    let mut listings = rootkit::Listings::default();
 
     for entry in walker {
         match entry {
@@ -93,16 +148,157 @@ async fn upsert_hash_tree(
                 warn!("{err}");
                 continue;
             }
-            Ok(value) => process_path(db, dispatcher, value.path()).await,
+            Ok(value) => {
+                #[cfg(target_os = "linux")]
+                if config.rootkit_checks {
+                    listings.add(&value);
+                }
+                process_path(db, dispatcher, value.path()).await
+            }
         };
     }
 
+    #[cfg(target_os = "linux")]
+    for finding in listings.check(config).iter() {
+        warn!("{finding}");
+        let _ = dispatcher
+            .dispatch(finding.into())
+            .await
+            .inspect_err(|e| error!("failed to dispatch message: {:?}", e));
+    }
+
     db.flush_async().await?;
     Ok(())
 }
 
-async fn process_event(event: Event, dispatcher: &SnitchDispatcher) {
+/// Determine how user accounts changed if `path` is an account file. The snapshot of `--init` is
+/// kept so that `--scan` still reports the changes.
+fn watched_account_changes(config: &Config, path: &Path) -> Vec<AccountChange> {
+    if !accounts::is_account_file(path) {
+        return vec![];
+    }
+    let db = match open_database(&config.database_path()) {
+        Ok(db) => db,
+        Err(err) => {
+            warn!("failed opening database: {err}");
+            return vec![];
+        }
+    };
+    accounts::diff_snapshot(&db, path)
+        .inspect_err(|e| warn!("failed comparing {:?}: {e}", path))
+        .ok()
+        .flatten()
+        .unwrap_or_default()
+}
+
+/// Returns `true` if `path` is below one of the configured directories.
+fn is_in_directories(config: &Config, path: &Path) -> bool {
+    config.directories().iter().any(|d| path.starts_with(d))
+}
+
+/// Paths outside of the configured directories which are watched for changes of
+/// `authorized_keys` files and, with the persistence profile, of autostart locations. Locations
+/// which do not exist yet are covered by watching their closest existing parent directory.
+fn extra_watches(config: &Config) -> Vec<(PathBuf, RecursiveMode)> {
+    let mut locations: Vec<PathBuf> = authorized_keys::all_user_files()
+        .inspect_err(|e| warn!("cannot list authorized_keys files: {e}"))
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+    if config.persistence {
+        locations.extend(persistence::locations());
+    }
+    let mut watches: Vec<(PathBuf, RecursiveMode)> = locations
+        .iter()
+        .filter(|location| !is_in_directories(config, location))
+        .filter_map(|location| match location.is_dir() {
+            true => Some((location.clone(), RecursiveMode::Recursive)),
+            false => location
+                .ancestors()
+                .skip(1)
+                .find(|d| d.is_dir())
+                .map(|d| (d.to_path_buf(), RecursiveMode::NonRecursive)),
+        })
+        .filter(|(path, _)| path != Path::new("/"))
+        .collect();
+    // a recursive watch covers a non-recursive one of the same directory
+    watches.sort_by_key(|(path, mode)| (path.clone(), *mode == RecursiveMode::NonRecursive));
+    watches.dedup_by(|a, b| a.0 == b.0);
+    watches
+}
+
+/// Compare the `authorized_keys` file at `path` with the baseline and dispatch the changed keys.
+/// `details` is appended to each message.
+async fn dispatch_key_changes(
+    config: &Config,
+    dispatcher: &SnitchDispatcher,
+    path: &Path,
+    details: Option<String>,
+) {
+    let changes = authorized_keys::diff_file(config, path)
+        .inspect_err(|e| warn!("failed comparing {:?}: {e}", path))
+        .unwrap_or_default();
+    for change in changes.iter() {
+        let mut message: MessageBackend = change.into();
+        if let Some(details) = &details {
+            message.body.push_str(&format!("\n{details}"));
+        }
+        let _ = dispatcher
+            .dispatch(message)
+            .await
+            .inspect_err(|e| error!("failed to dispatch message: {:?}", e));
+    }
+}
+
+async fn process_event(event: Event, config: &Config, dispatcher: &SnitchDispatcher) {
     debug!("processing event: {:?}", event);
+    let key_files: Vec<&PathBuf> = event
+        .paths
+        .iter()
+        .filter(|path| authorized_keys::is_authorized_keys_file(path))
+        .collect();
+    if !key_files.is_empty() {
+        for path in key_files {
+            dispatch_key_changes(config, dispatcher, path, None).await;
+        }
+        return;
+    }
+    if config.persistence {
+        let changes = PersistenceChange::from_event(&event.kind, &event.paths);
+        if !changes.is_empty() {
+            for change in changes.iter() {
+                let _ = dispatcher
+                    .dispatch(change.into())
+                    .await
+                    .inspect_err(|e| error!("failed to dispatch message: {:?}", e));
+            }
+            return;
+        }
+    }
+    // events of the directories watched for authorized_keys files and autostart locations only
+    if !event
+        .paths
+        .iter()
+        .any(|path| is_in_directories(config, path))
+    {
+        return;
+    }
+    if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+        let changes: Vec<AccountChange> = event
+            .paths
+            .iter()
+            .flat_map(|path| watched_account_changes(config, path))
+            .collect();
+        if !changes.is_empty() {
+            for change in changes.iter() {
+                let _ = dispatcher
+                    .dispatch(change.into())
+                    .await
+                    .inspect_err(|e| error!("failed to dispatch message: {:?}", e));
+            }
+            return;
+        }
+    }
     let _ = dispatcher
         .dispatch(event.into())
         .await
@@ -152,29 +348,385 @@ async fn process_path(db: &Db, dispatcher: &SnitchDispatcher, path: &Path) {
     };
 }
 
-pub async fn watch_files(config: &Config, dispatcher: &SnitchDispatcher) {
+/// Events received while watching files.
+enum WatchEvent {
+    Notify(notify::Result<Event>),
+    #[cfg(target_os = "linux")]
+    Fanotify(FanotifyEvent),
+    /// Events were lost and the given paths need to be rescanned.
+    Rescan(Vec<PathBuf>),
+}
+
+/// Start a fanotify watcher on the configured directories which reports the modifying process.
+/// Returns `false` if fanotify is unavailable and modifications need to be reported by notify.
+#[cfg(target_os = "linux")]
+fn start_fanotify(config: &Config, tx: Sender<WatchEvent>) -> bool {
+    let fanotify = match Fanotify::new() {
+        Ok(fanotify) => fanotify,
+        Err(err) => {
+            warn!("fanotify unavailable ({err}). Falling back to notify.");
+            return false;
+        }
+    };
+    let directories: Vec<PathBuf> = config
+        .directories()
+        .into_iter()
+        .map(Path::to_path_buf)
+        .collect();
+    for directory in directories.iter() {
+        if let Err(err) = fanotify.mark(directory) {
+            warn!("failed adding fanotify mark on {directory:?} ({err}). Falling back to notify.");
+            return false;
+        }
+    }
+
+    let own_pid = std::process::id() as i32;
+    std::thread::spawn(move || loop {
+        match fanotify.read_events() {
+            // interrupted by a signal before any event was read
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => {
+                error!("error while reading fanotify events {:?}", err);
+                return;
+            }
+            Ok(batch) => {
+                if batch.overflow && tx.send(WatchEvent::Rescan(directories.clone())).is_err() {
+                    return;
+                }
+                for event in batch.events {
+                    // marks cover whole mounts, only keep events below the watched directories
+                    if event.process.pid == own_pid
+                        || !directories.iter().any(|d| event.path.starts_with(d))
+                    {
+                        continue;
+                    }
+                    if tx.send(WatchEvent::Fanotify(event)).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    });
+    true
+}
+
+#[cfg(not(target_os = "linux"))]
+fn start_fanotify(_config: &Config, _tx: Sender<WatchEvent>) -> bool {
+    warn!("fanotify is only available on Linux. Falling back to notify.");
+    false
+}
+
+/// Compare the files below `start_path` with the hashes in `db` without updating them. Returns
+/// an event for each file that was created, modified or removed.
+async fn diff_hash_tree(
+    db: &Db,
+    config: &Config,
+    start_path: &Path,
+) -> Result<Vec<Event>, sled::Error> {
+    let mut events = vec![];
+    let mut seen = HashSet::new();
+    let walker = WalkDir::new(start_path)
+        .into_iter()
+        .filter_entry(|e| !config.is_excluded_directory(e))
+        .filter_map(Result::ok);
+    for entry in walker {
+        let path = entry.path();
+        if is_symlink_or_directory(path) {
+            continue;
+        }
+        let key = path.to_string_lossy().into_owned();
+        let hash = match hash_file(path).await {
+            Ok(hash) => hash,
+            Err(err) => {
+                warn!("{err} on {:?}. Skipping.", path);
+                seen.insert(key);
+                continue;
+            }
+        };
+        let kind = match db.get(key.as_bytes())? {
+            None => EventKind::Create(CreateKind::File),
+            Some(former) if former != hash.as_bytes() => {
+                EventKind::Modify(ModifyKind::Data(DataChange::Content))
+            }
+            Some(_) => {
+                seen.insert(key);
+                continue;
+            }
+        };
+        events.push(Event::new(kind).add_path(path.to_path_buf()));
+        seen.insert(key);
+    }
+    for entry in db.scan_prefix(start_path.to_string_lossy().as_bytes()) {
+        let (key, _) = entry?;
+        let path = PathBuf::from(String::from_utf8_lossy(&key).into_owned());
+        // the prefix also matches siblings like /etc2 of /etc
+        if !path.starts_with(start_path)
+            || seen.contains(path.to_string_lossy().as_ref())
+            || path.exists()
+        {
+            continue;
+        }
+        events.push(Event::new(EventKind::Remove(RemoveKind::File)).add_path(path));
+    }
+    Ok(events)
+}
+
+/// Compare all files below `paths` with the database and dispatch a message for each file that
+/// was created, modified or removed.
+async fn rescan(config: &Config, dispatcher: &SnitchDispatcher, paths: &[PathBuf]) {
+    info!("rescanning {:?}", paths);
+    let db = match open_database(&config.database_path()) {
+        Ok(db) => db,
+        Err(err) => {
+            error!("failed opening database for rescan: {err}");
+            return;
+        }
+    };
+    for path in paths {
+        match diff_hash_tree(&db, config, path).await {
+            Ok(events) => {
+                for event in events {
+                    process_event(event, config, dispatcher).await;
+                }
+            }
+            Err(err) => error!("failed rescanning {:?}: {err}", path),
+        }
+    }
+}
+
+/// Message sent if a directory cannot be watched because the OS watch limit is exhausted.
+fn watch_limit_message(directory: &Path, poll_interval: u64) -> MessageBackend {
+    MessageBackend::new_now(
+        "File watch limit reached".to_string(),
+        format!(
+            "Cannot watch {} for changes. Falling back to polling every {poll_interval}s.\n\
+            Consider increasing <code>fs.inotify.max_user_watches</code>.",
+            directory.display()
+        ),
+    )
+}
+
+pub async fn watch_files(
+    config: &Config,
+    dispatcher: &SnitchDispatcher,
+) -> Result<(), HashDBError> {
     // Create a channel to receive the events.
     let (tx, rx) = channel();
 
+    // fanotify reports which process modified a file. Modifications reported by notify are
+    // dropped if it is active.
+    let fanotify_active = config.fanotify && start_fanotify(config, tx.clone());
+
     // Create a watcher object, delivering raw events.
     // The notification back-end is selected based on the platform.
-    let mut watcher = notify::recommended_watcher(tx).unwrap();
+    let notify_tx = tx.clone();
+    // sending fails only while shutting down, when the receiver is gone
+    let mut watcher = notify::recommended_watcher(move |res| {
+        let _ = notify_tx.send(WatchEvent::Notify(res));
+    })?;
 
     // Add a path to be watched. All files and directories at that path and
-    // below will be monitored for changes.
+    // below will be monitored for changes. Directories exceeding the watch limit are polled.
+    let mut polled_directories = vec![];
     for directory in config.directories() {
         info!("adding watcher for {:?}", directory);
-        watcher.watch(directory, RecursiveMode::Recursive).unwrap();
+        match watcher.watch(directory, RecursiveMode::Recursive) {
+            Ok(_) => {}
+            Err(err) if matches!(err.kind, notify::ErrorKind::MaxFilesWatch) => {
+                warn!(
+                    "{err} while watching {:?}. Falling back to polling.",
+                    directory
+                );
+                // release the watches that were added before the limit was hit
+                let _ = watcher.unwatch(directory);
+                polled_directories.push(directory);
+                let _ = dispatcher
+                    .dispatch(watch_limit_message(directory, config.poll_interval))
+                    .await
+                    .inspect_err(|e| error!("failed to dispatch message: {:?}", e));
+            }
+            Err(err) => error!("failed watching {:?}: {err}", directory),
+        }
+    }
+
+    let mut watches = extra_watches(config);
+    for (path, mode) in watches.iter() {
+        info!("adding watcher for {:?}", path);
+        if let Err(err) = watcher.watch(path, *mode) {
+            error!("failed watching {:?}: {err}", path);
+        }
+    }
+
+    let poll_config =
+        notify::Config::default().with_poll_interval(Duration::from_secs(config.poll_interval));
+    let mut poll_watcher = PollWatcher::new(
+        move |res| {
+            let _ = tx.send(WatchEvent::Notify(res));
+        },
+        poll_config,
+    )?;
+    for directory in polled_directories {
+        info!("polling {:?} every {}s", directory, config.poll_interval);
+        if let Err(err) = poll_watcher.watch(directory, RecursiveMode::Recursive) {
+            error!("failed polling {:?}: {err}", directory);
+        }
     }
 
     for res in rx {
         match res {
-            Err(err) => {
+            WatchEvent::Notify(Err(err)) => {
                 error!("error while watching {:?}", err);
             }
-            Ok(event) => {
-                process_event(event, dispatcher).await;
+            WatchEvent::Notify(Ok(event)) if event.need_rescan() => {
+                warn!("file watcher lost events. Triggering rescan.");
+                let paths = match event.paths.is_empty() {
+                    true => config
+                        .directories()
+                        .into_iter()
+                        .map(Path::to_path_buf)
+                        .collect(),
+                    false => event.paths,
+                };
+                rescan(config, dispatcher, &paths).await;
+            }
+            // container files are compared by --scan
+            WatchEvent::Notify(Ok(event))
+                if event
+                    .paths
+                    .iter()
+                    .all(|path| config.containers.is_container_storage(path)) => {}
+            WatchEvent::Notify(Ok(event)) => {
+                if fanotify_active
+                    && matches!(event.kind, EventKind::Modify(ModifyKind::Data(_)))
+                    && event
+                        .paths
+                        .iter()
+                        .all(|path| is_in_directories(config, path))
+                {
+                    continue;
+                }
+                // newly created directories like .ssh replace their parent as watched path
+                if matches!(event.kind, EventKind::Create(_)) {
+                    let new_watches = extra_watches(config);
+                    for (path, mode) in new_watches.iter() {
+                        if watches.iter().any(|(watched, _)| watched == path) {
+                            continue;
+                        }
+                        info!("adding watcher for {:?}", path);
+                        if let Err(err) = watcher.watch(path, *mode) {
+                            error!("failed watching {:?}: {err}", path);
+                        }
+                    }
+                    watches = new_watches;
+                }
+                process_event(event, config, dispatcher).await;
+            }
+            #[cfg(target_os = "linux")]
+            WatchEvent::Fanotify(event) if config.containers.is_container_storage(&event.path) => {}
+            #[cfg(target_os = "linux")]
+            WatchEvent::Fanotify(event)
+                if authorized_keys::is_authorized_keys_file(&event.path) =>
+            {
+                let details = Some(event.process.to_string());
+                dispatch_key_changes(config, dispatcher, &event.path, details).await;
+            }
+            #[cfg(target_os = "linux")]
+            WatchEvent::Fanotify(event)
+                if config.persistence && Mechanism::of(&event.path).is_some() =>
+            {
+                if let Some(change) = PersistenceChange::new(&event.path, ChangeKind::Modified) {
+                    let mut message: MessageBackend = (&change).into();
+                    message.body.push_str(&format!("\n{}", event.process));
+                    let _ = dispatcher
+                        .dispatch(message)
+                        .await
+                        .inspect_err(|e| error!("failed to dispatch message: {:?}", e));
+                }
+            }
+            // modifications of account files are dropped from notify while fanotify is active
+            #[cfg(target_os = "linux")]
+            WatchEvent::Fanotify(event) if accounts::is_account_file(&event.path) => {
+                let changes = watched_account_changes(config, &event.path);
+                let messages: Vec<MessageBackend> = match changes.is_empty() {
+                    true => vec![event.into()],
+                    false => changes
+                        .iter()
+                        .map(|change| {
+                            let mut message: MessageBackend = change.into();
+                            message.body.push_str(&format!("\n{}", event.process));
+                            message
+                        })
+                        .collect(),
+                };
+                for message in messages {
+                    let _ = dispatcher
+                        .dispatch(message)
+                        .await
+                        .inspect_err(|e| error!("failed to dispatch message: {:?}", e));
+                }
+            }
+            #[cfg(target_os = "linux")]
+            WatchEvent::Fanotify(event) => {
+                debug!("processing fanotify event: {:?}", event);
+                let _ = dispatcher
+                    .dispatch(event.into())
+                    .await
+                    .inspect_err(|e| error!("failed to dispatch message: {:?}", e));
+            }
+            WatchEvent::Rescan(paths) => {
+                warn!("fanotify lost events. Triggering rescan.");
+                rescan(config, dispatcher, &paths).await;
             }
         }
     }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_diff_hash_tree() {
+        let directory = tempfile::tempdir().unwrap();
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let unchanged = directory.path().join("hosts");
+        let modified = directory.path().join("passwd");
+        let created = directory.path().join("crontab");
+        let removed = directory.path().join("shadow");
+        for path in [&unchanged, &modified] {
+            std::fs::write(path, "root").unwrap();
+            let hash = hash_file(path).await.unwrap();
+            db.insert(path.to_string_lossy().as_bytes(), hash.as_bytes())
+                .unwrap();
+        }
+        db.insert(removed.to_string_lossy().as_bytes(), "A")
+            .unwrap();
+        std::fs::write(&modified, "evil").unwrap();
+        std::fs::write(&created, "* * * * * curl").unwrap();
+
+        let events = diff_hash_tree(&db, &Config::demo_config(), directory.path())
+            .await
+            .unwrap();
+        let mut changes: Vec<(String, PathBuf)> = events
+            .into_iter()
+            .map(|event| {
+                (
+                    MessageBackend::from(event.clone()).title,
+                    event.paths[0].clone(),
+                )
+            })
+            .collect();
+        changes.sort();
+        assert_eq!(
+            changes,
+            vec![
+                ("created".to_string(), created),
+                ("modified".to_string(), modified),
+                ("removed".to_string(), removed),
+            ]
+        );
+        // the baseline is left untouched
+        assert_eq!(db.len(), 3);
+    }
 }
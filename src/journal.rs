@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::io;
+use std::process::Stdio;
+
+use chrono::{DateTime, Local, SecondsFormat};
+use tokio::io::AsyncReadExt;
+use tokio::process::{Child, ChildStdout, Command};
+
+/// Journal matches for authentication events. Matches of the same field are combined with OR,
+/// `+` combines the groups with OR.
+static MATCHES: [&str; 4] = [
+    "_SYSTEMD_UNIT=sshd.service",
+    "_SYSTEMD_UNIT=ssh.service",
+    "+",
+    "SYSLOG_IDENTIFIER=sudo",
+];
+
+/// A journal entry as a map from field names to values.
+#[derive(Debug, Default)]
+pub struct JournalEntry {
+    pub fields: HashMap<String, String>,
+}
+
+impl JournalEntry {
+    /// The cursor which identifies the position of this entry in the journal.
+    pub fn cursor(&self) -> Option<&str> {
+        self.fields.get("__CURSOR").map(String::as_str)
+    }
+
+    /// Format the entry like a line written by syslog so that it can be parsed like entries of
+    /// authentication log files.
+    pub fn to_syslog_line(&self) -> Option<String> {
+        let message = self.fields.get("MESSAGE")?;
+        let timestamp = self
+            .fields
+            .get("__REALTIME_TIMESTAMP")
+            .and_then(|micros| micros.parse::<i64>().ok())
+            .and_then(DateTime::from_timestamp_micros)?
+            .with_timezone(&Local);
+        let hostname = self.fields.get("_HOSTNAME").map_or("localhost", |h| h);
+        let identifier = self
+            .fields
+            .get("SYSLOG_IDENTIFIER")
+            .map_or("unknown", |i| i);
+        let pid = self
+            .fields
+            .get("SYSLOG_PID")
+            .map(|pid| format!("[{pid}]"))
+            .unwrap_or_default();
+        Some(format!(
+            "{} {hostname} {identifier}{pid}: {message}",
+            timestamp.to_rfc3339_opts(SecondsFormat::Micros, false)
+        ))
+    }
+}
+
+/// Incremental parser of the journal export format (`journalctl -o export`). Entries are
+/// separated by empty lines. Fields are either `NAME=value` lines or, for values that are not
+/// printable, the name followed by a newline, the little endian 64 bit length and the data.
+#[derive(Default)]
+pub struct ExportParser {
+    buffer: Vec<u8>,
+    entry: JournalEntry,
+}
+
+impl ExportParser {
+    /// Feed `data` into the parser and return all entries that were completed by it.
+    pub fn feed(&mut self, data: &[u8]) -> Vec<JournalEntry> {
+        self.buffer.extend_from_slice(data);
+        let mut entries = vec![];
+        let mut consumed = 0;
+
+        loop {
+            let remaining = &self.buffer[consumed..];
+            let Some(end) = remaining.iter().position(|byte| *byte == b'\n') else {
+                break;
+            };
+            let line = &remaining[..end];
+            if line.is_empty() {
+                consumed += 1;
+                if !self.entry.fields.is_empty() {
+                    entries.push(std::mem::take(&mut self.entry));
+                }
+                continue;
+            }
+
+            if let Some(separator) = line.iter().position(|byte| *byte == b'=') {
+                let name = String::from_utf8_lossy(&line[..separator]).into_owned();
+                let value = String::from_utf8_lossy(&line[separator + 1..]).into_owned();
+                self.entry.fields.insert(name, value);
+                consumed += end + 1;
+                continue;
+            }
+
+            // binary field: name, newline, 64 bit length, data, newline
+            let data_start = end + 1 + 8;
+            if remaining.len() < data_start {
+                break;
+            }
+            let length = u64::from_le_bytes(remaining[end + 1..data_start].try_into().unwrap());
+            let data_end = data_start + length as usize;
+            if remaining.len() < data_end + 1 {
+                break;
+            }
+            let name = String::from_utf8_lossy(line).into_owned();
+            let value = String::from_utf8_lossy(&remaining[data_start..data_end]).into_owned();
+            self.entry.fields.insert(name, value);
+            consumed += data_end + 1;
+        }
+
+        self.buffer.drain(..consumed);
+        entries
+    }
+}
+
+/// Follows authentication related entries of the systemd journal through `journalctl`.
+pub struct Journal {
+    // keeps journalctl running until the journal is dropped
+    _child: Child,
+    stdout: ChildStdout,
+    parser: ExportParser,
+}
+
+impl Journal {
+    /// Follow the journal starting after `cursor` or, if there is none, at its end.
+    pub fn follow(cursor: Option<&str>) -> io::Result<Journal> {
+        let mut command = Command::new("journalctl");
+        command.args(["--output=export", "--follow"]);
+        match cursor {
+            Some(cursor) => command.arg(format!("--after-cursor={cursor}")),
+            None => command.arg("--lines=0"),
+        };
+        let mut child = command
+            .args(MATCHES)
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+        Ok(Journal {
+            _child: child,
+            stdout,
+            parser: ExportParser::default(),
+        })
+    }
+
+    /// All authentication related entries currently in the journal.
+    pub async fn history() -> io::Result<Vec<JournalEntry>> {
+        let output = Command::new("journalctl")
+            .arg("--output=export")
+            .args(MATCHES)
+            .stderr(Stdio::null())
+            .output()
+            .await?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "journalctl exited with {}",
+                output.status
+            )));
+        }
+        Ok(ExportParser::default().feed(&output.stdout))
+    }
+
+    /// Wait for new entries.
+    pub async fn read_entries(&mut self) -> io::Result<Vec<JournalEntry>> {
+        let mut chunk = vec![0; 8192];
+        loop {
+            let count = self.stdout.read(&mut chunk).await?;
+            if count == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "journalctl exited",
+                ));
+            }
+            let entries = self.parser.feed(&chunk[..count]);
+            if !entries.is_empty() {
+                return Ok(entries);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_export() {
+        let data = std::fs::read("test/journal.export").unwrap();
+        let mut parser = ExportParser::default();
+        // feeding in chunks must not make a difference
+        let entries: Vec<JournalEntry> = data
+            .chunks(7)
+            .flat_map(|chunk| parser.feed(chunk))
+            .collect();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[2].fields["MESSAGE"], "binary\nmessage");
+        assert!(entries[0]
+            .to_syslog_line()
+            .unwrap()
+            .ends_with("blabla-hostname sshd[955719]: Accepted publickey for demo-user from 12.23.43.12 port 32123 ssh2: RSA SHA256:ilijsdf+/lijsdf//sdflijjidj1123E5gKg"));
+    }
+}
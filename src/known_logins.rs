@@ -0,0 +1,150 @@
+use std::fmt;
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use sled::Db;
+
+use crate::config::Config;
+use crate::dispatcher::Severity;
+use crate::persist::PersistError;
+
+/// Tree of the authentication watcher's database holding the logins seen per host and user.
+static KNOWN_LOGINS_TREE: &str = "known_logins";
+
+/// Prefix lengths sources are grouped by. Addresses in the same network count as known.
+const IPV4_PREFIX: u8 = 24;
+const IPV6_PREFIX: u8 = 64;
+
+/// A combination of source and authentication method a user logged in with before.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct KnownLogin {
+    /// Network of the source address or the hostname if sshd logged one.
+    pub source: String,
+    pub method: String,
+}
+
+/// How unusual a login is compared to the logins seen before.
+#[derive(Debug, PartialEq)]
+pub enum Assessment {
+    /// The source address is in `login_allowlist`.
+    Allowlisted,
+    Known,
+    /// The user never logged in from this source.
+    NewSource,
+    /// The user never used this authentication method, e.g. `password` where only `publickey`
+    /// was seen before.
+    NewMethod,
+}
+
+impl Assessment {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Assessment::Allowlisted => Severity::Info,
+            Assessment::Known => Severity::Warning,
+            Assessment::NewSource | Assessment::NewMethod => Severity::High,
+        }
+    }
+}
+
+impl fmt::Display for Assessment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            Assessment::Allowlisted => "source is allowlisted",
+            Assessment::Known => "known source and method",
+            Assessment::NewSource => "first login of this user from this source",
+            Assessment::NewMethod => "first login of this user with this method",
+        };
+        write!(f, "{description}")
+    }
+}
+
+/// The network `ip` is grouped into.
+fn network(ip: IpAddr) -> IpNet {
+    let prefix = match ip {
+        IpAddr::V4(_) => IPV4_PREFIX,
+        IpAddr::V6(_) => IPV6_PREFIX,
+    };
+    IpNet::new(ip, prefix).expect("valid prefix length").trunc()
+}
+
+fn assess_history(history: &[KnownLogin], login: &KnownLogin) -> Assessment {
+    if !history.iter().any(|known| known.source == login.source) {
+        return Assessment::NewSource;
+    }
+    if !history.iter().any(|known| known.method == login.method) {
+        return Assessment::NewMethod;
+    }
+    Assessment::Known
+}
+
+/// Assess a login of `username` on `hostname` against the allowlist and the logins seen before
+/// and remember it in `state`.
+pub fn assess_login(
+    config: &Config,
+    state: &Db,
+    hostname: &str,
+    username: &str,
+    ip: Option<IpAddr>,
+    remote_host: Option<&str>,
+    method: &str,
+) -> Result<Assessment, PersistError> {
+    if let Some(ip) = ip {
+        if config.login_allowlist.iter().any(|net| net.contains(&ip)) {
+            return Ok(Assessment::Allowlisted);
+        }
+    }
+    let source = match (ip, remote_host) {
+        (Some(ip), _) => network(ip).to_string(),
+        (None, Some(remote_host)) => remote_host.to_string(),
+        (None, None) => "unknown".to_string(),
+    };
+    let login = KnownLogin {
+        source,
+        method: method.to_string(),
+    };
+
+    let tree = state.open_tree(KNOWN_LOGINS_TREE)?;
+    let key = format!("{hostname}/{username}");
+    let mut history: Vec<KnownLogin> = tree
+        .get(key.as_bytes())?
+        .and_then(|value| serde_json::from_slice(&value).ok())
+        .unwrap_or_default();
+
+    let assessment = assess_history(&history, &login);
+    if assessment != Assessment::Known {
+        history.push(login);
+        let value = serde_json::to_vec(&history).expect("serializable history");
+        tree.insert(key.as_bytes(), value)?;
+    }
+    Ok(assessment)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assess_history() {
+        let known = |source: &str, method: &str| KnownLogin {
+            source: source.to_string(),
+            method: method.to_string(),
+        };
+        let source = network("12.23.43.12".parse().unwrap()).to_string();
+        assert_eq!(source, "12.23.43.0/24");
+
+        let history = vec![known(&source, "publickey")];
+        assert_eq!(
+            assess_history(&history, &known("12.23.43.0/24", "publickey")),
+            Assessment::Known
+        );
+        assert_eq!(
+            assess_history(&history, &known("12.23.43.0/24", "password")),
+            Assessment::NewMethod
+        );
+        assert_eq!(
+            assess_history(&history, &known("2001:db8::/64", "publickey")),
+            Assessment::NewSource
+        );
+    }
+}
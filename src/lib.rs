@@ -1,11 +1,33 @@
 #[macro_use]
 extern crate log;
 
+pub mod accounts;
+pub mod audit;
 pub mod authentication_logs;
+pub mod authorized_keys;
+pub mod brute_force;
 pub mod cli;
 pub mod config;
+pub mod containers;
 mod dispatcher;
+pub mod enrichment;
+#[cfg(target_os = "linux")]
+pub mod fanotify;
+pub mod file_attributes;
 pub mod hashing;
+pub mod journal;
+pub mod known_logins;
+pub mod network;
+pub mod permissions;
 pub mod persist;
+pub mod persistence;
+#[cfg(target_os = "linux")]
+pub mod processes;
+#[cfg(target_os = "linux")]
+pub mod rootkit;
+pub mod rules;
+pub mod sessions;
 pub mod style;
+pub mod system;
+pub mod tail;
 pub mod test_utils;
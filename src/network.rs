@@ -0,0 +1,448 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::time;
+
+use crate::config::Config;
+use crate::dispatcher::{MessageBackend, Severity, SnitchDispatcher};
+#[cfg(target_os = "linux")]
+use crate::fanotify::ProcessInfo;
+use crate::persist::{open_state_database, PersistError};
+
+/// Tree of the state database holding the listeners seen so far. Listeners are only added, so
+/// that one which is opened and closed repeatedly is reported once.
+static LISTENERS_TREE: &str = "listeners";
+
+/// Socket tables and the protocol of their sockets.
+static SOCKET_TABLES: [(&str, Protocol); 4] = [
+    ("/proc/net/tcp", Protocol::Tcp),
+    ("/proc/net/tcp6", Protocol::Tcp),
+    ("/proc/net/udp", Protocol::Udp),
+    ("/proc/net/udp6", Protocol::Udp),
+];
+
+/// Ports the kernel picks for sockets which are not bound explicitly.
+static LOCAL_PORT_RANGE: &str = "/proc/sys/net/ipv4/ip_local_port_range";
+const DEFAULT_LOCAL_PORT_RANGE: RangeInclusive<u16> = 32768..=60999;
+
+const TCP_LISTEN: u8 = 0x0A;
+/// Unconnected UDP sockets are in state `TCP_CLOSE`.
+const UDP_UNCONNECTED: u8 = 0x07;
+
+#[derive(Debug, Error)]
+pub enum NetworkError {
+    #[error(transparent)]
+    Sled(#[from] sled::Error),
+    #[error(transparent)]
+    Persist(#[from] PersistError),
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Protocol::Tcp => write!(f, "tcp"),
+            Protocol::Udp => write!(f, "udp"),
+        }
+    }
+}
+
+/// A socket accepting connections or datagrams.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listener {
+    pub protocol: Protocol,
+    pub address: SocketAddr,
+    pub uid: u32,
+    pub inode: u64,
+}
+
+impl Listener {
+    /// The key of the listener in the state database, e.g. `tcp 0.0.0.0:22`.
+    pub fn key(&self) -> String {
+        format!("{} {}", self.protocol, self.address)
+    }
+}
+
+/// Decode an address like `0100007F:0035`. The kernel prints each 32 bit word of the address
+/// in host byte order.
+fn parse_address(address: &str) -> Option<SocketAddr> {
+    let (ip, port) = address.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    let mut octets = vec![];
+    for index in (0..ip.len()).step_by(8) {
+        let word = u32::from_str_radix(ip.get(index..index + 8)?, 16).ok()?;
+        octets.extend(word.to_ne_bytes());
+    }
+    let ip = match octets.len() {
+        4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(octets).ok()?)),
+        16 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(octets).ok()?)),
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+/// The range of ephemeral ports from `ip_local_port_range`.
+fn local_port_range() -> RangeInclusive<u16> {
+    std::fs::read_to_string(LOCAL_PORT_RANGE)
+        .ok()
+        .and_then(|contents| {
+            let mut ports = contents.split_whitespace().map(|port| port.parse().ok());
+            Some(ports.next()??..=ports.next()??)
+        })
+        .unwrap_or(DEFAULT_LOCAL_PORT_RANGE)
+}
+
+/// Parse the listening sockets of a table like `/proc/net/tcp`. Unconnected UDP sockets on
+/// `ephemeral` ports are clients sending with `sendto`, which the kernel bound to a random port.
+pub fn parse_sockets(
+    contents: &str,
+    protocol: Protocol,
+    ephemeral: &RangeInclusive<u16>,
+) -> Vec<Listener> {
+    contents
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let address = parse_address(fields.get(1)?)?;
+            let remote = parse_address(fields.get(2)?)?;
+            let state = u8::from_str_radix(fields.get(3)?, 16).ok()?;
+            let listening = match protocol {
+                Protocol::Tcp => state == TCP_LISTEN,
+                Protocol::Udp => {
+                    state == UDP_UNCONNECTED
+                        && remote.port() == 0
+                        && !ephemeral.contains(&address.port())
+                }
+            };
+            // port 0 means the socket is not bound
+            if !listening || address.port() == 0 {
+                return None;
+            }
+            Some(Listener {
+                protocol,
+                address,
+                uid: fields.get(7)?.parse().ok()?,
+                inode: fields.get(9)?.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// Pid and executable of processes by the inodes of the sockets they hold open.
+#[cfg(target_os = "linux")]
+fn socket_owners() -> BTreeMap<u64, (i32, Option<PathBuf>)> {
+    let mut owners = BTreeMap::new();
+    let pids = std::fs::read_dir("/proc")
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<i32>().ok());
+    for pid in pids {
+        // fails for processes of other users without root
+        let Ok(fds) = std::fs::read_dir(format!("/proc/{pid}/fd")) else {
+            continue;
+        };
+        for fd in fds.filter_map(Result::ok) {
+            let Ok(target) = std::fs::read_link(fd.path()) else {
+                continue;
+            };
+            let inode = target
+                .to_str()
+                .and_then(|target| target.strip_prefix("socket:["))
+                .and_then(|target| target.strip_suffix(']'))
+                .and_then(|inode| inode.parse().ok());
+            if let Some(inode) = inode {
+                owners
+                    .entry(inode)
+                    .or_insert_with(|| (pid, ProcessInfo::from_pid(pid).exe));
+            }
+        }
+    }
+    owners
+}
+
+#[cfg(not(target_os = "linux"))]
+fn socket_owners() -> BTreeMap<u64, (i32, Option<PathBuf>)> {
+    BTreeMap::new()
+}
+
+/// The program owning a listener as stored in the state database.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Owner {
+    pub uid: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exe: Option<String>,
+}
+
+/// A listener with the process owning it, if it could be determined.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OwnedListener {
+    pub listener: Listener,
+    pub pid: Option<i32>,
+    pub owner: Owner,
+}
+
+/// Collect the current listeners and the processes owning them.
+pub fn collect() -> Vec<OwnedListener> {
+    let owners = socket_owners();
+    let ephemeral = local_port_range();
+    let mut listeners: Vec<OwnedListener> = SOCKET_TABLES
+        .iter()
+        .filter_map(|(path, protocol)| {
+            std::fs::read_to_string(path)
+                .map(|contents| parse_sockets(&contents, *protocol, &ephemeral))
+                .ok()
+        })
+        .flatten()
+        .map(|listener| {
+            let process = owners.get(&listener.inode);
+            OwnedListener {
+                pid: process.map(|(pid, _)| *pid),
+                owner: Owner {
+                    uid: listener.uid,
+                    exe: process
+                        .and_then(|(_, exe)| exe.as_ref())
+                        .map(|exe| exe.display().to_string()),
+                },
+                listener,
+            }
+        })
+        .collect();
+    listeners.sort_by_key(|listener| (listener.listener.key(), listener.pid));
+    // several sockets can share an address, e.g. with SO_REUSEPORT
+    listeners.dedup_by_key(|listener| listener.listener.key());
+    listeners
+}
+
+/// A listener which was not seen before or is now served by another program.
+#[derive(Debug, PartialEq)]
+pub enum ListenerChange {
+    New(OwnedListener),
+    OwnerChanged { listener: OwnedListener, old: Owner },
+}
+
+impl ListenerChange {
+    fn listener(&self) -> &OwnedListener {
+        match self {
+            ListenerChange::New(listener) => listener,
+            ListenerChange::OwnerChanged { listener, .. } => listener,
+        }
+    }
+
+    /// Listeners on loopback addresses cannot be reached from other hosts.
+    pub fn severity(&self) -> Severity {
+        match self.listener().listener.address.ip().is_loopback() {
+            true => Severity::Warning,
+            false => Severity::High,
+        }
+    }
+}
+
+impl fmt::Display for ListenerChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let listener = self.listener();
+        let exe = listener.owner.exe.as_deref().unwrap_or("unknown program");
+        write!(
+            f,
+            "<b>{}</b> served by <code>{exe}</code>",
+            listener.listener.key()
+        )?;
+        if let Some(pid) = listener.pid {
+            write!(f, " (pid {pid}, uid {})", listener.owner.uid)?;
+        } else {
+            write!(f, " (uid {})", listener.owner.uid)?;
+        }
+        if let ListenerChange::OwnerChanged { old, .. } = self {
+            let old_exe = old.exe.as_deref().unwrap_or("unknown program");
+            write!(f, "\npreviously <code>{old_exe}</code> (uid {})", old.uid)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<&ListenerChange> for MessageBackend {
+    fn from(value: &ListenerChange) -> Self {
+        let title = match value {
+            ListenerChange::New(_) => "New network listener",
+            ListenerChange::OwnerChanged { .. } => "Network listener changed",
+        };
+        MessageBackend::new_now(title.to_string(), value.to_string())
+            .with_severity(value.severity())
+    }
+}
+
+/// Compare `current` with the listeners seen so far.
+pub fn diff(known: &BTreeMap<String, Owner>, current: &[OwnedListener]) -> Vec<ListenerChange> {
+    current
+        .iter()
+        .filter_map(|listener| match known.get(&listener.listener.key()) {
+            None => Some(ListenerChange::New(listener.clone())),
+            // the program is unknown if it could not be read, e.g. without root
+            Some(old)
+                if old.exe.is_some()
+                    && listener.owner.exe.is_some()
+                    && old.exe != listener.owner.exe =>
+            {
+                Some(ListenerChange::OwnerChanged {
+                    listener: listener.clone(),
+                    old: old.clone(),
+                })
+            }
+            Some(_) => None,
+        })
+        .collect()
+}
+
+fn load(tree: &sled::Tree) -> Result<BTreeMap<String, Owner>, NetworkError> {
+    tree.iter()
+        .map(|entry| {
+            let (key, value) = entry?;
+            Ok((
+                String::from_utf8_lossy(&key).into_owned(),
+                serde_json::from_slice(&value)?,
+            ))
+        })
+        .collect()
+}
+
+fn store(tree: &sled::Tree, listeners: &[OwnedListener]) -> Result<(), NetworkError> {
+    for listener in listeners {
+        tree.insert(
+            listener.listener.key().as_bytes(),
+            serde_json::to_vec(&listener.owner)?,
+        )?;
+    }
+    Ok(())
+}
+
+/// Replace the known listeners by the current ones.
+pub fn record_baseline(config: &Config) -> Result<(), NetworkError> {
+    let db = open_state_database(config)?;
+    let tree = db.open_tree(LISTENERS_TREE)?;
+    tree.clear()?;
+    store(&tree, &collect())?;
+    db.flush()?;
+    Ok(())
+}
+
+/// Compare the current listeners with the known ones. If `update` is set, they are added to the
+/// known listeners. Without a baseline the current listeners are recorded silently.
+pub fn check(config: &Config, update: bool) -> Result<Vec<ListenerChange>, NetworkError> {
+    let db = open_state_database(config)?;
+    let tree = db.open_tree(LISTENERS_TREE)?;
+    let current = collect();
+    if tree.is_empty() {
+        store(&tree, &current)?;
+        db.flush()?;
+        return Ok(vec![]);
+    }
+    let changes = diff(&load(&tree)?, &current);
+    if update {
+        let changed: Vec<OwnedListener> = changes
+            .iter()
+            .map(|change| change.listener().clone())
+            .collect();
+        store(&tree, &changed)?;
+        db.flush()?;
+    }
+    Ok(changes)
+}
+
+/// Check for new listeners every `network_interval` seconds.
+pub async fn watch_listeners(config: &Config, dispatcher: &SnitchDispatcher) {
+    info!("start watching network listeners");
+    let mut interval = time::interval(Duration::from_secs(config.network_interval));
+    loop {
+        interval.tick().await;
+        let changes = match check(config, true) {
+            Ok(changes) => changes,
+            Err(e) => {
+                error!("failed checking network listeners: {e}");
+                continue;
+            }
+        };
+        for change in changes.iter() {
+            warn!("{change}");
+            let _ = dispatcher
+                .dispatch(change.into())
+                .await
+                .inspect_err(|e| error!("failed to dispatch message: {:?}", e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sockets() {
+        let tcp = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000:0016 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 18342 1 0000000000000000 100 0 0 10 0
+   1: 0100007F:0035 00000000:0000 0A 00000000:00000000 00:00000000 00000000   101        0 17201 1 0000000000000000 100 0 0 10 0
+   2: 0F02000A:0016 0202000A:D1C4 01 00000000:00000000 02:0007A3B2 00000000     0        0 48211 2 0000000000000000 20 4 29 10 -1";
+        let listeners = parse_sockets(tcp, Protocol::Tcp, &DEFAULT_LOCAL_PORT_RANGE);
+        assert_eq!(listeners.len(), 2);
+        assert_eq!(listeners[0].key(), "tcp 0.0.0.0:22");
+        assert_eq!(listeners[1].key(), "tcp 127.0.0.1:53");
+        assert_eq!(listeners[1].uid, 101);
+        assert_eq!(listeners[1].inode, 17201);
+
+        let udp6 = "  sl  local_address                         remote_address                        st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode ref pointer drops
+  512: 00000000000000000000000001000000:115C 00000000000000000000000000000000:0000 07 00000000:00000000 00:00000000 00000000     0        0 9121 2 0000000000000000 0
+  513: 00000000000000000000000000000000:A4F1 00000000000000000000000000000000:0000 07 00000000:00000000 00:00000000 00000000  1000        0 9188 2 0000000000000000 0";
+        let listeners = parse_sockets(udp6, Protocol::Udp, &DEFAULT_LOCAL_PORT_RANGE);
+        // port 42225 of the second socket is ephemeral
+        assert_eq!(listeners.len(), 1);
+        assert_eq!(listeners[0].key(), "udp [::1]:4444");
+    }
+
+    #[test]
+    fn test_diff() {
+        let listener = |port, exe: &str| OwnedListener {
+            listener: Listener {
+                protocol: Protocol::Tcp,
+                address: SocketAddr::from(([0, 0, 0, 0], port)),
+                uid: 0,
+                inode: 1,
+            },
+            pid: Some(4242),
+            owner: Owner {
+                uid: 0,
+                exe: Some(exe.to_string()),
+            },
+        };
+        let known = BTreeMap::from([(
+            "tcp 0.0.0.0:22".to_string(),
+            listener(22, "/usr/sbin/sshd").owner,
+        )]);
+        let changes = diff(
+            &known,
+            &[listener(22, "/tmp/sshd"), listener(4444, "/tmp/.x/sh")],
+        );
+        assert_eq!(changes.len(), 2);
+        assert_eq!(
+            changes[1].to_string(),
+            "<b>tcp 0.0.0.0:4444</b> served by <code>/tmp/.x/sh</code> (pid 4242, uid 0)"
+        );
+        let message: MessageBackend = (&changes[0]).into();
+        assert_eq!(message.title, "Network listener changed");
+        assert_eq!(message.severity, Severity::High);
+    }
+}
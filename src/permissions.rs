@@ -0,0 +1,316 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::Metadata;
+
+use serde::{Deserialize, Serialize};
+use sled::Db;
+use thiserror::Error;
+use walkdir::WalkDir;
+
+use crate::accounts;
+use crate::config::Config;
+use crate::dispatcher::{MessageBackend, Severity};
+
+/// Tree of the hash database holding the setuid, setgid and world-writable files of the
+/// baseline.
+static SPECIAL_FILES_TREE: &str = "special_files";
+/// Key in the special files tree telling that an inventory was recorded, even an empty one.
+/// Paths are absolute and never collide with it.
+static RECORDED_KEY: &str = "recorded";
+
+const SETUID: u32 = 0o4000;
+const SETGID: u32 = 0o2000;
+const STICKY: u32 = 0o1000;
+const WORLD_WRITABLE: u32 = 0o0002;
+
+#[derive(Debug, Error)]
+pub enum PermissionsError {
+    #[error(transparent)]
+    Sled(#[from] sled::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// Mode and owner of a setuid, setgid or world-writable file or directory.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct SpecialFile {
+    pub mode: u32,
+    pub uid: u32,
+    pub is_dir: bool,
+}
+
+impl SpecialFile {
+    #[cfg(unix)]
+    fn from_metadata(metadata: &Metadata) -> Option<SpecialFile> {
+        use std::os::unix::fs::MetadataExt;
+
+        // symbolic links always have mode 777
+        if metadata.is_symlink() {
+            return None;
+        }
+        let file = SpecialFile {
+            mode: metadata.mode() & 0o7777,
+            uid: metadata.uid(),
+            is_dir: metadata.is_dir(),
+        };
+        (file.is_setuid() || file.is_setgid() || file.is_world_writable()).then_some(file)
+    }
+
+    #[cfg(not(unix))]
+    fn from_metadata(_metadata: &Metadata) -> Option<SpecialFile> {
+        None
+    }
+
+    pub fn is_setuid(&self) -> bool {
+        self.mode & SETUID != 0
+    }
+
+    pub fn is_setgid(&self) -> bool {
+        self.mode & SETGID != 0
+    }
+
+    pub fn is_world_writable(&self) -> bool {
+        self.mode & WORLD_WRITABLE != 0
+    }
+
+    /// Setuid and world-writable files are the most dangerous additions, world-writable
+    /// directories with sticky bit like `/tmp` are the least.
+    fn severity(&self) -> Severity {
+        if self.is_setuid() || (self.is_world_writable() && !self.is_dir) {
+            Severity::Critical
+        } else if self.is_setgid() || self.mode & STICKY == 0 {
+            Severity::High
+        } else {
+            Severity::Warning
+        }
+    }
+
+    fn flags(&self) -> String {
+        let mut flags = vec![];
+        if self.is_setuid() {
+            flags.push("setuid");
+        }
+        if self.is_setgid() {
+            flags.push("setgid");
+        }
+        if self.is_world_writable() {
+            flags.push("world-writable");
+        }
+        flags.join(",")
+    }
+}
+
+impl fmt::Display for SpecialFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:04o} uid {}", self.flags(), self.mode, self.uid)
+    }
+}
+
+/// Special files by path.
+pub type Inventory = BTreeMap<String, SpecialFile>;
+
+/// Find the setuid, setgid and world-writable files and directories below the configured
+/// directories.
+pub fn collect(config: &Config) -> Inventory {
+    let mut inventory = Inventory::new();
+    for directory in config.directories() {
+        let walker = WalkDir::new(directory)
+            .into_iter()
+            .filter_entry(|e| !config.is_excluded_directory(e));
+        for entry in walker.filter_map(Result::ok) {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if let Some(file) = SpecialFile::from_metadata(&metadata) {
+                inventory.insert(entry.path().to_string_lossy().into_owned(), file);
+            }
+        }
+    }
+    inventory
+}
+
+/// Replace the inventory of the baseline in `db`.
+pub fn record(db: &Db, inventory: &Inventory) -> Result<(), PermissionsError> {
+    let tree = db.open_tree(SPECIAL_FILES_TREE)?;
+    tree.clear()?;
+    tree.insert(RECORDED_KEY, &[])?;
+    for (path, file) in inventory {
+        tree.insert(path.as_bytes(), serde_json::to_vec(file)?)?;
+    }
+    Ok(())
+}
+
+/// Read the inventory of the baseline from `db`. Returns `None` if none was recorded.
+pub fn load(db: &Db) -> Result<Option<Inventory>, PermissionsError> {
+    let tree = db.open_tree(SPECIAL_FILES_TREE)?;
+    if !tree.contains_key(RECORDED_KEY)? {
+        return Ok(None);
+    }
+    tree.iter()
+        .filter(|entry| entry.as_ref().map_or(true, |(key, _)| *key != RECORDED_KEY))
+        .map(|entry| {
+            let (path, file) = entry?;
+            Ok((
+                String::from_utf8_lossy(&path).into_owned(),
+                serde_json::from_slice(&file)?,
+            ))
+        })
+        .collect::<Result<Inventory, PermissionsError>>()
+        .map(Some)
+}
+
+/// A change of the set of special files compared to the baseline.
+#[derive(Debug, PartialEq)]
+pub enum PermissionChange {
+    Added {
+        path: String,
+        file: SpecialFile,
+    },
+    Removed {
+        path: String,
+        file: SpecialFile,
+    },
+    Changed {
+        path: String,
+        old: SpecialFile,
+        new: SpecialFile,
+    },
+}
+
+impl PermissionChange {
+    pub fn severity(&self) -> Severity {
+        match self {
+            PermissionChange::Added { file, .. } => file.severity(),
+            PermissionChange::Changed { old, new, .. } if old.flags() != new.flags() => {
+                new.severity()
+            }
+            _ => Severity::Warning,
+        }
+    }
+}
+
+impl fmt::Display for PermissionChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PermissionChange::Added { path, file } => {
+                write!(f, "New {} <code>{path}</code> ({file})", file.flags())
+            }
+            PermissionChange::Removed { path, file } => {
+                write!(f, "<code>{path}</code> is no longer {}", file.flags())
+            }
+            PermissionChange::Changed { path, old, new } => {
+                write!(f, "<code>{path}</code> changed: {old} → {new}")
+            }
+        }
+    }
+}
+
+impl From<&PermissionChange> for MessageBackend {
+    fn from(value: &PermissionChange) -> Self {
+        MessageBackend::new_now("Permissions changed".to_string(), value.to_string())
+            .with_severity(value.severity())
+    }
+}
+
+pub fn diff(old: &Inventory, new: &Inventory) -> Vec<PermissionChange> {
+    let mut changes = vec![];
+    for (path, file) in new {
+        match old.get(path) {
+            None => changes.push(PermissionChange::Added {
+                path: path.clone(),
+                file: *file,
+            }),
+            Some(old) if old != file => changes.push(PermissionChange::Changed {
+                path: path.clone(),
+                old: *old,
+                new: *file,
+            }),
+            Some(_) => {}
+        }
+    }
+    for (path, file) in old {
+        if !new.contains_key(path) {
+            changes.push(PermissionChange::Removed {
+                path: path.clone(),
+                file: *file,
+            });
+        }
+    }
+    changes
+}
+
+/// Print the current setuid, setgid and world-writable files.
+pub fn print_inventory(config: &Config) {
+    let users = std::fs::read_to_string(accounts::PASSWD)
+        .map(|contents| accounts::parse_passwd(&contents))
+        .unwrap_or_default();
+    let names: BTreeMap<u32, String> = users
+        .into_iter()
+        .map(|(name, user)| (user.uid, name))
+        .collect();
+    for (path, file) in collect(config) {
+        let owner = names
+            .get(&file.uid)
+            .cloned()
+            .unwrap_or_else(|| file.uid.to_string());
+        println!("{:04o} {owner:<12} {:<24} {path}", file.mode, file.flags());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let file = |mode| SpecialFile {
+            mode,
+            uid: 0,
+            is_dir: false,
+        };
+        let old = Inventory::from([
+            ("/usr/bin/passwd".to_string(), file(0o4755)),
+            ("/usr/bin/wall".to_string(), file(0o2755)),
+        ]);
+        let new = Inventory::from([
+            ("/usr/bin/passwd".to_string(), file(0o4755)),
+            ("/tmp/.x/bash".to_string(), file(0o4755)),
+            ("/usr/bin/wall".to_string(), file(0o2757)),
+        ]);
+        let changes = diff(&old, &new);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(
+            changes[0],
+            PermissionChange::Added {
+                path: "/tmp/.x/bash".to_string(),
+                file: file(0o4755)
+            }
+        );
+        assert_eq!(changes[0].severity(), Severity::Critical);
+        assert_eq!(changes[1].severity(), Severity::Critical);
+        assert_eq!(
+            changes[1].to_string(),
+            "<code>/usr/bin/wall</code> changed: setgid 2755 uid 0 → setgid,world-writable 2757 uid 0"
+        );
+    }
+
+    #[test]
+    fn test_record_and_load() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        assert_eq!(load(&db).unwrap(), None);
+
+        record(&db, &Inventory::new()).unwrap();
+        assert_eq!(load(&db).unwrap(), Some(Inventory::new()));
+
+        let inventory = Inventory::from([(
+            "/usr/bin/passwd".to_string(),
+            SpecialFile {
+                mode: 0o4755,
+                uid: 0,
+                is_dir: false,
+            },
+        )]);
+        record(&db, &inventory).unwrap();
+        assert_eq!(load(&db).unwrap(), Some(inventory));
+    }
+}
@@ -1,5 +1,17 @@
+use crate::accounts;
+use crate::authorized_keys;
 use crate::config::Config;
+use crate::containers;
+use crate::file_attributes;
 use crate::hashing;
+use crate::network;
+use crate::permissions;
+use crate::persistence::{self, ChangeKind, PersistenceChange};
+#[cfg(target_os = "linux")]
+use crate::processes;
+#[cfg(target_os = "linux")]
+use crate::rootkit;
+use crate::system;
 use std::fmt;
 use std::path::{Path, PathBuf};
 use std::str::from_utf8;
@@ -59,6 +71,17 @@ pub fn open_database(path: &PathBuf) -> Result<Db, PersistError> {
     Ok(db)
 }
 
+/// Open the state database. It is only kept open while reading or writing so that other snitch
+/// processes can access it, too.
+pub fn open_state_database(config: &Config) -> Result<Db, PersistError> {
+    open_database(&config.state_database_path())
+}
+
+/// Open the database of `watcher`, which it keeps open while watching.
+pub fn open_watcher_database(config: &Config, watcher: &str) -> Result<Db, PersistError> {
+    open_database(&config.watcher_database_path(watcher))
+}
+
 pub fn upsert_hashes(db: &sled::Db, fp: &Path, file_hash: &str) -> Result<(), HashMismatch> {
     debug!("upserting hash for {:?}", fp);
     let file_path = fp.to_str().unwrap();
@@ -90,11 +113,22 @@ pub async fn validate_hashes(
         let former_hash = from_utf8(&vec.1)?;
 
         let fp = Path::new(&vec_str);
+        // compared key by key below
+        if authorized_keys::is_authorized_keys_file(fp) {
+            continue;
+        }
+        // compared including the contents of initial ramdisks below
+        if system::is_boot_artifact(fp) {
+            continue;
+        }
         if !fp.exists() {
-            let message = MessageBackend::new_now(
-                "File/directory removed".to_string(),
-                fp.to_str().unwrap().to_string(),
-            );
+            let message = match persistence_change(config, fp, ChangeKind::Removed) {
+                Some(change) => (&change).into(),
+                None => MessageBackend::new_now(
+                    "File/directory removed".to_string(),
+                    fp.to_str().unwrap().to_string(),
+                ),
+            };
             dispatcher.dispatch(message).await?;
             continue;
         }
@@ -103,16 +137,123 @@ pub async fn validate_hashes(
             Ok(_) => {}
             Err(e) => {
                 warn!("{:?}", e);
-                dispatcher.dispatch(e.into()).await?;
+                let changes = match accounts::is_account_file(fp) {
+                    true => accounts::diff_snapshot(&db, fp)
+                        .inspect_err(|e| warn!("failed comparing {:?}: {e}", fp))
+                        .ok()
+                        .flatten()
+                        .unwrap_or_default(),
+                    false => vec![],
+                };
+                if let Some(change) = persistence_change(config, fp, ChangeKind::Modified) {
+                    dispatcher.dispatch((&change).into()).await?;
+                } else if changes.is_empty() {
+                    dispatcher.dispatch(e.into()).await?;
+                }
+                for change in changes.iter() {
+                    dispatcher.dispatch(change.into()).await?;
+                }
             }
         }
     }
     progressbar.finish_with_message("done");
+
+    if config.persistence {
+        for change in persistence::unknown_files(&db)?.iter() {
+            dispatcher.dispatch(change.into()).await?;
+        }
+    }
+
+    match permissions::load(&db) {
+        Ok(Some(baseline)) => {
+            for change in permissions::diff(&baseline, &permissions::collect(config)).iter() {
+                dispatcher.dispatch(change.into()).await?;
+            }
+        }
+        Ok(None) => info!("no setuid/setgid inventory recorded. Run --init to record one."),
+        Err(e) => warn!("failed comparing setuid/setgid inventory: {e}"),
+    }
+
+    match file_attributes::load(&db) {
+        Ok(baseline) => {
+            let current = file_attributes::collect(config);
+            for change in file_attributes::diff(&baseline, &current).iter() {
+                dispatcher.dispatch(change.into()).await?;
+            }
+        }
+        Err(e) => warn!("failed comparing file attributes: {e}"),
+    }
+
+    #[cfg(target_os = "linux")]
+    match processes::scan(&db).await {
+        Ok(anomalies) => {
+            for anomaly in anomalies.iter() {
+                dispatcher.dispatch(anomaly.into()).await?;
+            }
+        }
+        Err(e) => warn!("failed scanning processes: {e}"),
+    }
+
+    #[cfg(target_os = "linux")]
+    if config.rootkit_checks {
+        let findings = rootkit::hidden_files(config)
+            .into_iter()
+            .chain(rootkit::hidden_processes());
+        for finding in findings {
+            dispatcher.dispatch((&finding).into()).await?;
+        }
+    }
+
+    if config.containers.enabled {
+        match containers::scan(&db, &config.containers).await {
+            Ok(changes) => {
+                for change in changes.iter() {
+                    dispatcher.dispatch(change.into()).await?;
+                }
+            }
+            Err(e) => warn!("failed comparing containers: {e}"),
+        }
+    }
+
+    match system::check(config, false) {
+        Ok(changes) => {
+            for change in changes.iter() {
+                dispatcher.dispatch(change.into()).await?;
+            }
+        }
+        Err(e) => warn!("failed comparing kernel modules and boot files: {e}"),
+    }
+
+    match network::check(config, false) {
+        Ok(changes) => {
+            for change in changes.iter() {
+                dispatcher.dispatch(change.into()).await?;
+            }
+        }
+        Err(e) => warn!("failed comparing network listeners: {e}"),
+    }
+
+    match authorized_keys::scan(config) {
+        Ok(changes) => {
+            for change in changes.iter() {
+                dispatcher.dispatch(change.into()).await?;
+            }
+        }
+        Err(e) => warn!("failed comparing authorized_keys files: {e}"),
+    }
     info!("database checksum: {}", db.checksum()?);
 
     Ok(())
 }
 
+/// The change of `path` if the persistence profile is enabled and it is an autostart location.
+fn persistence_change(config: &Config, path: &Path, kind: ChangeKind) -> Option<PersistenceChange> {
+    match config.persistence {
+        true => PersistenceChange::new(path, kind),
+        false => None,
+    }
+}
+
 async fn validate_hash(fp: &Path, former_hash: &str) -> Result<(), HashMismatch> {
     if !fp.exists() {
         warn!("the file does not exist anymore! {:?}", fp)
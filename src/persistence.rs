@@ -0,0 +1,398 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use notify::event::ModifyKind;
+use notify::EventKind;
+use sled::{Db, Tree};
+use walkdir::WalkDir;
+
+use crate::accounts;
+use crate::dispatcher::{MessageBackend, Severity};
+
+/// System wide autostart locations watched by the persistence profile.
+static SYSTEM_LOCATIONS: [&str; 10] = [
+    "/etc/crontab",
+    "/etc/cron.d",
+    "/etc/cron.hourly",
+    "/etc/cron.daily",
+    "/etc/cron.weekly",
+    "/etc/cron.monthly",
+    "/var/spool/cron",
+    "/etc/systemd/system",
+    "/etc/ld.so.preload",
+    "/etc/rc.local",
+];
+
+/// Directory of the systemd units of a user relative to their home directory.
+static USER_UNIT_DIRECTORY: &str = ".config/systemd/user";
+
+/// Shell startup files relative to the home directory of each user.
+static SHELL_RC_FILES: [&str; 5] = [
+    ".bashrc",
+    ".bash_profile",
+    ".bash_login",
+    ".profile",
+    ".zshrc",
+];
+
+/// Tree of the hash database holding the targets of symbolic links in autostart locations, e.g.
+/// the units enabled by links in `.wants` directories. Files are hashed, links are not.
+static LINKS_TREE: &str = "persistence_links";
+
+/// A way of starting code automatically which attackers use to persist on a host.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mechanism {
+    Cron,
+    SystemdUnit,
+    ShellRc,
+    LdPreload,
+    RcLocal,
+}
+
+impl Mechanism {
+    /// The mechanism `path` belongs to, if any.
+    pub fn of(path: &Path) -> Option<Mechanism> {
+        if path == Path::new("/etc/ld.so.preload") {
+            return Some(Mechanism::LdPreload);
+        }
+        if path == Path::new("/etc/rc.local") {
+            return Some(Mechanism::RcLocal);
+        }
+        let in_cron_directory = path.parent().is_some_and(|parent| {
+            parent.parent() == Some(Path::new("/etc"))
+                && parent
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with("cron."))
+        });
+        if path == Path::new("/etc/crontab")
+            || in_cron_directory
+            || path.starts_with("/var/spool/cron")
+        {
+            return Some(Mechanism::Cron);
+        }
+        let in_user_units = path
+            .ancestors()
+            .any(|ancestor| ancestor.ends_with(USER_UNIT_DIRECTORY));
+        if path.starts_with("/etc/systemd/system") || in_user_units {
+            return Some(Mechanism::SystemdUnit);
+        }
+        let file_name = path.file_name()?.to_string_lossy();
+        if SHELL_RC_FILES.contains(&file_name.as_ref()) {
+            return Some(Mechanism::ShellRc);
+        }
+        None
+    }
+
+    fn severity(&self) -> Severity {
+        match self {
+            Mechanism::LdPreload => Severity::Critical,
+            _ => Severity::High,
+        }
+    }
+}
+
+impl fmt::Display for Mechanism {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Mechanism::Cron => "cron job",
+            Mechanism::SystemdUnit => "systemd unit",
+            Mechanism::ShellRc => "shell startup file",
+            Mechanism::LdPreload => "ld.so.preload",
+            Mechanism::RcLocal => "rc.local",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Removed,
+}
+
+/// A change of an autostart location.
+#[derive(Debug, PartialEq)]
+pub struct PersistenceChange {
+    pub mechanism: Mechanism,
+    pub path: PathBuf,
+    pub kind: ChangeKind,
+}
+
+impl PersistenceChange {
+    /// The change of `path` if it is an autostart location. Directories are ignored.
+    pub fn new(path: &Path, kind: ChangeKind) -> Option<PersistenceChange> {
+        if path.is_dir() {
+            return None;
+        }
+        Some(PersistenceChange {
+            mechanism: Mechanism::of(path)?,
+            path: path.to_path_buf(),
+            kind,
+        })
+    }
+
+    /// The changes of autostart locations reported by a notify event.
+    pub fn from_event(kind: &EventKind, paths: &[PathBuf]) -> Vec<PersistenceChange> {
+        paths
+            .iter()
+            .filter_map(|path| {
+                let kind = match kind {
+                    EventKind::Create(_) => ChangeKind::Created,
+                    EventKind::Remove(_) => ChangeKind::Removed,
+                    // renames are reported for the old and the new name
+                    EventKind::Modify(ModifyKind::Name(_)) => match path.exists() {
+                        true => ChangeKind::Created,
+                        false => ChangeKind::Removed,
+                    },
+                    EventKind::Modify(_) => ChangeKind::Modified,
+                    _ => return None,
+                };
+                PersistenceChange::new(path, kind)
+            })
+            .collect()
+    }
+
+    /// A short description like "New systemd unit enabled: evil.service".
+    pub fn title(&self) -> String {
+        let name = self
+            .path
+            .file_name()
+            .map_or(self.path.to_string_lossy(), |name| name.to_string_lossy());
+        // units are enabled by linking them into a .wants or .requires directory
+        let enabled = self.mechanism == Mechanism::SystemdUnit
+            && self
+                .path
+                .parent()
+                .and_then(Path::file_name)
+                .is_some_and(|parent| {
+                    let parent = parent.to_string_lossy();
+                    parent.ends_with(".wants") || parent.ends_with(".requires")
+                });
+        let mechanism = self.mechanism;
+        if matches!(mechanism, Mechanism::LdPreload | Mechanism::RcLocal) {
+            let verb = match self.kind {
+                ChangeKind::Created => "created",
+                ChangeKind::Modified => "modified",
+                ChangeKind::Removed => "removed",
+            };
+            return format!("{} {verb}", self.path.display());
+        }
+        match (self.kind, enabled) {
+            (ChangeKind::Created, true) => format!("New systemd unit enabled: {name}"),
+            (ChangeKind::Removed, true) => format!("Systemd unit disabled: {name}"),
+            (ChangeKind::Created, false) => format!("New {mechanism}: {name}"),
+            (ChangeKind::Modified, _) => format!("Modified {mechanism}: {name}"),
+            (ChangeKind::Removed, false) => format!("Removed {mechanism}: {name}"),
+        }
+    }
+}
+
+impl From<&PersistenceChange> for MessageBackend {
+    fn from(value: &PersistenceChange) -> Self {
+        MessageBackend::new_now(value.title(), value.path.display().to_string())
+            .with_severity(value.mechanism.severity())
+    }
+}
+
+/// All autostart locations of the system and the users in `/etc/passwd`, whether they exist
+/// or not.
+pub fn locations() -> Vec<PathBuf> {
+    let mut locations: Vec<PathBuf> = SYSTEM_LOCATIONS.iter().map(PathBuf::from).collect();
+    let homes = std::fs::read_to_string(accounts::PASSWD)
+        .map(|contents| accounts::parse_passwd(&contents))
+        .inspect_err(|e| warn!("cannot read users: {e}"))
+        .unwrap_or_default()
+        .into_values()
+        .map(|user| PathBuf::from(user.home));
+    for home in homes {
+        locations.push(home.join(USER_UNIT_DIRECTORY));
+        locations.extend(SHELL_RC_FILES.iter().map(|file| home.join(file)));
+    }
+    locations.sort();
+    locations.dedup();
+    locations
+}
+
+/// Record the targets of the symbolic links below `location` in the hash database `db`.
+pub fn record_links(db: &Db, location: &Path) -> Result<(), sled::Error> {
+    let links = db.open_tree(LINKS_TREE)?;
+    for entry in WalkDir::new(location).into_iter().filter_map(Result::ok) {
+        if !entry.path_is_symlink() {
+            continue;
+        }
+        match std::fs::read_link(entry.path()) {
+            Ok(target) => {
+                links.insert(
+                    entry.path().to_string_lossy().as_bytes(),
+                    target.to_string_lossy().as_bytes(),
+                )?;
+            }
+            Err(e) => warn!("cannot read link {:?}: {e}", entry.path()),
+        }
+    }
+    Ok(())
+}
+
+/// Files and links below `location` which are not part of the baseline in `db` and links whose
+/// target changed.
+fn unknown_files_below(
+    db: &Db,
+    links: &Tree,
+    location: &Path,
+) -> Result<Vec<PersistenceChange>, sled::Error> {
+    let mut changes = vec![];
+    for entry in WalkDir::new(location).into_iter().filter_map(Result::ok) {
+        let path = entry.path();
+        let key = path.to_string_lossy();
+        let kind = if entry.path_is_symlink() {
+            let Ok(target) = std::fs::read_link(path) else {
+                continue;
+            };
+            match links.get(key.as_bytes())? {
+                None => ChangeKind::Created,
+                Some(recorded) if *recorded != *target.to_string_lossy().as_bytes() => {
+                    ChangeKind::Modified
+                }
+                Some(_) => continue,
+            }
+        } else if entry.file_type().is_file() && !db.contains_key(key.as_bytes())? {
+            ChangeKind::Created
+        } else {
+            continue;
+        };
+        changes.extend(PersistenceChange::new(path, kind));
+    }
+    Ok(changes)
+}
+
+/// Files and links in autostart locations which are not part of the baseline in `db`, links
+/// whose target changed and removed links.
+pub fn unknown_files(db: &Db) -> Result<Vec<PersistenceChange>, sled::Error> {
+    let links = db.open_tree(LINKS_TREE)?;
+    let mut changes = vec![];
+    for location in locations() {
+        changes.extend(unknown_files_below(db, &links, &location)?);
+    }
+    changes.extend(removed_links(&links)?);
+    Ok(changes)
+}
+
+/// Links of the baseline which no longer exist, e.g. of disabled units.
+fn removed_links(links: &Tree) -> Result<Vec<PersistenceChange>, sled::Error> {
+    let mut changes = vec![];
+    for entry in links.iter() {
+        let (key, _) = entry?;
+        let path = PathBuf::from(String::from_utf8_lossy(&key).into_owned());
+        if path.symlink_metadata().is_err() {
+            changes.extend(PersistenceChange::new(&path, ChangeKind::Removed));
+        }
+    }
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mechanism() {
+        let mechanism = |path: &str| Mechanism::of(Path::new(path));
+        assert_eq!(mechanism("/etc/cron.d/backup"), Some(Mechanism::Cron));
+        assert_eq!(
+            mechanism("/var/spool/cron/crontabs/root"),
+            Some(Mechanism::Cron)
+        );
+        assert_eq!(
+            mechanism("/home/user1/.config/systemd/user/agent.service"),
+            Some(Mechanism::SystemdUnit)
+        );
+        assert_eq!(mechanism("/root/.bashrc"), Some(Mechanism::ShellRc));
+        assert_eq!(mechanism("/etc/ld.so.preload"), Some(Mechanism::LdPreload));
+        assert_eq!(mechanism("/etc/hosts"), None);
+    }
+
+    #[test]
+    fn test_title() {
+        let change = |path: &str, kind| PersistenceChange {
+            mechanism: Mechanism::of(Path::new(path)).unwrap(),
+            path: PathBuf::from(path),
+            kind,
+        };
+        assert_eq!(
+            change(
+                "/etc/systemd/system/multi-user.target.wants/evil.service",
+                ChangeKind::Created
+            )
+            .title(),
+            "New systemd unit enabled: evil.service"
+        );
+        assert_eq!(
+            change("/etc/cron.d/backup", ChangeKind::Modified).title(),
+            "Modified cron job: backup"
+        );
+        let message: MessageBackend = (&change("/etc/ld.so.preload", ChangeKind::Created)).into();
+        assert_eq!(message.title, "/etc/ld.so.preload created");
+        assert_eq!(message.severity, Severity::Critical);
+    }
+
+    #[test]
+    fn test_unknown_files() {
+        let home = tempfile::tempdir().unwrap();
+        let units = home.path().join(USER_UNIT_DIRECTORY);
+        let wants = units.join("default.target.wants");
+        std::fs::create_dir_all(&wants).unwrap();
+        std::fs::write(units.join("agent.service"), "").unwrap();
+        std::os::unix::fs::symlink(units.join("agent.service"), wants.join("agent.service"))
+            .unwrap();
+        std::os::unix::fs::symlink(units.join("agent.service"), wants.join("moved.service"))
+            .unwrap();
+        std::os::unix::fs::symlink(units.join("agent.service"), wants.join("gone.service"))
+            .unwrap();
+
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        db.insert(
+            units.join("agent.service").to_string_lossy().as_bytes(),
+            "A",
+        )
+        .unwrap();
+        record_links(&db, &units).unwrap();
+        let links = db.open_tree(LINKS_TREE).unwrap();
+        assert!(unknown_files_below(&db, &links, &units).unwrap().is_empty());
+
+        std::fs::write(units.join("evil.service"), "").unwrap();
+        std::os::unix::fs::symlink(units.join("evil.service"), wants.join("evil.service")).unwrap();
+        std::fs::remove_file(wants.join("moved.service")).unwrap();
+        std::os::unix::fs::symlink(units.join("evil.service"), wants.join("moved.service"))
+            .unwrap();
+        std::fs::remove_file(wants.join("gone.service")).unwrap();
+        let mut changes: Vec<(String, ChangeKind)> = unknown_files_below(&db, &links, &units)
+            .unwrap()
+            .into_iter()
+            .chain(removed_links(&links).unwrap())
+            .map(|change| (change.title(), change.kind))
+            .collect();
+        changes.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            changes,
+            vec![
+                (
+                    "Modified systemd unit: moved.service".to_string(),
+                    ChangeKind::Modified
+                ),
+                (
+                    "New systemd unit enabled: evil.service".to_string(),
+                    ChangeKind::Created
+                ),
+                (
+                    "New systemd unit: evil.service".to_string(),
+                    ChangeKind::Created
+                ),
+                (
+                    "Systemd unit disabled: gone.service".to_string(),
+                    ChangeKind::Removed
+                ),
+            ]
+        );
+    }
+}
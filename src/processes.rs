@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use sled::Db;
+
+use crate::dispatcher::{escape_html, MessageBackend, Severity};
+use crate::fanotify::ProcessInfo;
+use crate::hashing;
+
+/// Directories which are writable by everyone and where legitimate programs are not installed.
+static TEMPORARY_DIRECTORIES: [&str; 3] = ["/tmp", "/var/tmp", "/dev/shm"];
+
+/// The kernel appends this to the `exe` link of a process whose executable was deleted.
+const DELETED_SUFFIX: &str = " (deleted)";
+
+/// Executables created with `memfd_create` only exist in memory.
+const MEMFD_PREFIX: &str = "/memfd:";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Anomaly {
+    /// The executable was deleted after the process started. This also happens to long running
+    /// processes after a package upgrade.
+    DeletedExecutable,
+    /// The executable was never on disk.
+    InMemoryExecutable,
+    TemporaryLocation,
+    /// The executable differs from the file hashed in the baseline.
+    HashMismatch {
+        expected: String,
+        actual: String,
+    },
+}
+
+impl Anomaly {
+    fn severity(&self) -> Severity {
+        match self {
+            Anomaly::DeletedExecutable => Severity::High,
+            _ => Severity::Critical,
+        }
+    }
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Anomaly::DeletedExecutable => write!(f, "executable was deleted"),
+            Anomaly::InMemoryExecutable => write!(f, "executable only exists in memory"),
+            Anomaly::TemporaryLocation => write!(f, "runs from a temporary directory"),
+            Anomaly::HashMismatch { expected, actual } => write!(
+                f,
+                "executable differs from the baseline\nexpected: {expected}\nactual: {actual}"
+            ),
+        }
+    }
+}
+
+/// A process that shows signs of malware running from memory or outside of the installed
+/// programs.
+#[derive(Debug, Clone)]
+pub struct ProcessAnomaly {
+    pub process: ProcessInfo,
+    /// The path of the executable without the `(deleted)` suffix.
+    pub executable: PathBuf,
+    pub anomaly: Anomaly,
+}
+
+impl fmt::Display for ProcessAnomaly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "<code>{}</code> {}\n{}",
+            escape_html(&self.executable.to_string_lossy()),
+            self.anomaly,
+            self.process
+        )
+    }
+}
+
+impl From<&ProcessAnomaly> for MessageBackend {
+    fn from(value: &ProcessAnomaly) -> Self {
+        MessageBackend::new_now("Suspicious process".to_string(), value.to_string())
+            .with_severity(value.anomaly.severity())
+    }
+}
+
+/// Split the target of an `exe` link into the path of the executable and the anomalies which
+/// can be told from the path alone.
+pub fn inspect_executable(exe: &Path) -> (PathBuf, Vec<Anomaly>) {
+    let link = exe.to_string_lossy();
+    let (path, deleted) = match link.strip_suffix(DELETED_SUFFIX) {
+        Some(path) => (PathBuf::from(path), true),
+        None => (exe.to_path_buf(), false),
+    };
+    let mut anomalies = vec![];
+    if link.starts_with(MEMFD_PREFIX) {
+        anomalies.push(Anomaly::InMemoryExecutable);
+    } else if deleted {
+        anomalies.push(Anomaly::DeletedExecutable);
+    }
+    if TEMPORARY_DIRECTORIES
+        .iter()
+        .any(|directory| path.starts_with(directory))
+    {
+        anomalies.push(Anomaly::TemporaryLocation);
+    }
+    (path, anomalies)
+}
+
+/// Scan the running processes for deleted or in-memory executables, executables in temporary
+/// directories and executables whose hash differs from the one recorded in the hash database
+/// `db`.
+pub async fn scan(db: &Db) -> Result<Vec<ProcessAnomaly>, sled::Error> {
+    let own_pid = std::process::id() as i32;
+    let pids = std::fs::read_dir("/proc")
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<i32>().ok())
+        .filter(|pid| *pid != own_pid);
+    // many processes share an executable
+    let mut hashes: HashMap<PathBuf, Option<String>> = HashMap::new();
+    let mut anomalies = vec![];
+    for pid in pids {
+        let process = ProcessInfo::from_pid(pid);
+        // kernel threads have no executable
+        let Some(exe) = &process.exe else {
+            continue;
+        };
+        let (executable, mut found) = inspect_executable(exe);
+        if found.is_empty() {
+            if let Some(expected) = db.get(executable.to_string_lossy().as_bytes())? {
+                let expected = String::from_utf8_lossy(&expected).into_owned();
+                if !hashes.contains_key(&executable) {
+                    // hash the file the process was started from, not the one on disk now
+                    let exe_link = Path::new("/proc").join(pid.to_string()).join("exe");
+                    let hash = hashing::hash_file(&exe_link)
+                        .await
+                        .inspect_err(|e| debug!("cannot hash executable of {pid}: {e}"))
+                        .ok();
+                    hashes.insert(executable.clone(), hash);
+                }
+                if let Some(Some(actual)) = hashes.get(&executable) {
+                    if *actual != expected {
+                        found.push(Anomaly::HashMismatch {
+                            expected,
+                            actual: actual.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        anomalies.extend(found.into_iter().map(|anomaly| ProcessAnomaly {
+            process: process.clone(),
+            executable: executable.clone(),
+            anomaly,
+        }));
+    }
+    Ok(anomalies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inspect_executable() {
+        assert_eq!(
+            inspect_executable(Path::new("/usr/sbin/sshd")),
+            (PathBuf::from("/usr/sbin/sshd"), vec![])
+        );
+        assert_eq!(
+            inspect_executable(Path::new("/dev/shm/.x/kworker (deleted)")),
+            (
+                PathBuf::from("/dev/shm/.x/kworker"),
+                vec![Anomaly::DeletedExecutable, Anomaly::TemporaryLocation]
+            )
+        );
+        assert_eq!(
+            inspect_executable(Path::new("/memfd:payload (deleted)")).1,
+            vec![Anomaly::InMemoryExecutable]
+        );
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use walkdir::{DirEntry, WalkDir};
+
+use crate::config::Config;
+use crate::dispatcher::{escape_html, MessageBackend, Severity};
+use crate::fanotify::ProcessInfo;
+
+const BUFFER_SIZE: usize = 32768;
+/// Offset of `d_name` in `struct linux_dirent64`.
+const DIRENT_NAME_OFFSET: usize = 19;
+const DEFAULT_PID_MAX: i32 = 32768;
+
+/// Something hidden from the usual ways of listing files and processes, which is what rootkits
+/// do.
+#[derive(Debug)]
+pub enum Finding {
+    /// Entries returned by the `getdents64` syscall but missing from the directory walk, e.g.
+    /// because `readdir` is hooked by a preloaded library.
+    HiddenEntries {
+        directory: PathBuf,
+        names: Vec<OsString>,
+    },
+    /// A directory has more hard links than it has subdirectories plus `.` and its entry in the
+    /// parent. Also detects subdirectories hidden by a kernel rootkit which hooks `getdents64`.
+    LinkCountMismatch {
+        directory: PathBuf,
+        links: u64,
+        subdirectories: usize,
+    },
+    /// A process which answers `kill(pid, 0)` but is missing from `/proc`.
+    HiddenProcess(ProcessInfo),
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Finding::HiddenEntries { directory, names } => {
+                write!(
+                    f,
+                    "Hidden in <code>{}</code>:",
+                    escape_html(&directory.to_string_lossy())
+                )?;
+                for name in names {
+                    write!(f, "\n<code>{}</code>", escape_html(&name.to_string_lossy()))?;
+                }
+                Ok(())
+            }
+            Finding::LinkCountMismatch {
+                directory,
+                links,
+                subdirectories,
+            } => write!(
+                f,
+                "<code>{}</code> has {links} links but only {subdirectories} visible subdirectories",
+                escape_html(&directory.to_string_lossy())
+            ),
+            Finding::HiddenProcess(process) => write!(f, "Hidden process\n{process}"),
+        }
+    }
+}
+
+impl From<&Finding> for MessageBackend {
+    fn from(value: &Finding) -> Self {
+        MessageBackend::new_now("Possible rootkit".to_string(), value.to_string())
+            .with_severity(Severity::Critical)
+    }
+}
+
+/// Parse a buffer filled by `getdents64` into the names of the entries.
+fn parse_dirents(buffer: &[u8], names: &mut BTreeSet<OsString>) {
+    let mut offset = 0;
+    while offset + DIRENT_NAME_OFFSET <= buffer.len() {
+        let record_length = u16::from_ne_bytes([buffer[offset + 16], buffer[offset + 17]]) as usize;
+        if record_length == 0 {
+            break;
+        }
+        let end = (offset + record_length).min(buffer.len());
+        let name = &buffer[offset + DIRENT_NAME_OFFSET..end];
+        let name = &name[..name
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(name.len())];
+        if name != b"." && name != b".." {
+            names.insert(OsStr::from_bytes(name).to_os_string());
+        }
+        offset += record_length;
+    }
+}
+
+/// List `directory` with the `getdents64` syscall, bypassing `readdir` of the C library.
+pub fn getdents(directory: &Path) -> io::Result<BTreeSet<OsString>> {
+    let file = File::open(directory)?;
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    let mut names = BTreeSet::new();
+    loop {
+        // SAFETY: the descriptor is valid while `file` lives, the kernel writes at most
+        // `buffer.len()` bytes
+        let count = unsafe {
+            libc::syscall(
+                libc::SYS_getdents64,
+                file.as_raw_fd(),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+            )
+        };
+        match count {
+            0 => return Ok(names),
+            count if count < 0 => return Err(io::Error::last_os_error()),
+            count => parse_dirents(&buffer[..count as usize], &mut names),
+        }
+    }
+}
+
+/// Names and types of the entries of each directory seen by a directory walk.
+#[derive(Debug, Default)]
+pub struct Listings {
+    directories: BTreeMap<PathBuf, BTreeMap<OsString, bool>>,
+}
+
+impl Listings {
+    pub fn add(&mut self, entry: &DirEntry) {
+        let is_dir = entry.file_type().is_dir();
+        if is_dir {
+            self.directories
+                .entry(entry.path().to_path_buf())
+                .or_default();
+        }
+        if entry.depth() == 0 {
+            return;
+        }
+        if let Some(parent) = entry.path().parent() {
+            self.directories
+                .entry(parent.to_path_buf())
+                .or_default()
+                .insert(entry.file_name().to_os_string(), is_dir);
+        }
+    }
+
+    /// Compare the walked directories with `getdents64` and their link counts. Entries created
+    /// since the walk are ignored by listing the directory again.
+    pub fn check(&self, config: &Config) -> Vec<Finding> {
+        let mut findings = vec![];
+        for (directory, walked) in self.directories.iter() {
+            // the contents of the databases and the sessions file change while checking
+            if directory.starts_with(&config.snitch_root) {
+                continue;
+            }
+            // unreadable directories cannot be compared
+            let Ok(entries) = std::fs::read_dir(directory) else {
+                continue;
+            };
+            let relisted: BTreeMap<OsString, bool> = entries
+                .filter_map(Result::ok)
+                .map(|entry| {
+                    let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+                    (entry.file_name(), is_dir)
+                })
+                .collect();
+            match getdents(directory) {
+                Ok(names) => {
+                    let hidden: Vec<OsString> = names
+                        .into_iter()
+                        .filter(|name| !walked.contains_key(name) && !relisted.contains_key(name))
+                        .collect();
+                    if !hidden.is_empty() {
+                        findings.push(Finding::HiddenEntries {
+                            directory: directory.clone(),
+                            names: hidden,
+                        });
+                    }
+                }
+                Err(e) => debug!("cannot list {directory:?} with getdents64: {e}"),
+            }
+            let Ok(metadata) = std::fs::symlink_metadata(directory) else {
+                continue;
+            };
+            let subdirectories = walked
+                .values()
+                .filter(|is_dir| **is_dir)
+                .count()
+                .max(relisted.values().filter(|is_dir| **is_dir).count());
+            if has_hidden_subdirectories(metadata.nlink(), subdirectories) {
+                findings.push(Finding::LinkCountMismatch {
+                    directory: directory.clone(),
+                    links: metadata.nlink(),
+                    subdirectories,
+                });
+            }
+        }
+        findings
+    }
+}
+
+/// Walk the configured directories and compare them with `getdents64` and their link counts.
+pub fn hidden_files(config: &Config) -> Vec<Finding> {
+    let mut listings = Listings::default();
+    for directory in config.directories() {
+        WalkDir::new(directory)
+            .into_iter()
+            .filter_entry(|e| !config.is_excluded_directory(e))
+            .filter_map(Result::ok)
+            .for_each(|entry| listings.add(&entry));
+    }
+    listings.check(config)
+}
+
+/// Some file systems like btrfs do not count the links of subdirectories and always report one
+/// link.
+fn has_hidden_subdirectories(links: u64, subdirectories: usize) -> bool {
+    links > 1 && links > subdirectories as u64 + 2
+}
+
+fn listed_pids() -> BTreeSet<i32> {
+    std::fs::read_dir("/proc")
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+        .collect()
+}
+
+fn exists(pid: i32) -> bool {
+    // SAFETY: signal 0 sends nothing, it only checks whether the process exists
+    let result = unsafe { libc::kill(pid, 0) };
+    result == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// The thread group of `pid`. Threads answer `kill` but are not listed in `/proc`.
+fn thread_group(pid: i32) -> Option<i32> {
+    std::fs::read_to_string(format!("/proc/{pid}/status"))
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("Tgid:"))?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Find processes which exist according to `kill(pid, 0)` but are missing from `/proc`.
+pub fn hidden_processes() -> Vec<Finding> {
+    let pid_max = std::fs::read_to_string("/proc/sys/kernel/pid_max")
+        .ok()
+        .and_then(|pid_max| pid_max.trim().parse().ok())
+        .unwrap_or(DEFAULT_PID_MAX);
+    let listed = listed_pids();
+    let candidates: Vec<i32> = (1..=pid_max)
+        .filter(|pid| !listed.contains(pid) && exists(*pid))
+        .collect();
+    if candidates.is_empty() {
+        return vec![];
+    }
+    // processes started during probing
+    let relisted = listed_pids();
+    candidates
+        .into_iter()
+        .filter(|pid| !relisted.contains(pid))
+        .filter(|pid| thread_group(*pid).is_none_or(|tgid| tgid == *pid))
+        .filter(|pid| exists(*pid))
+        .map(|pid| Finding::HiddenProcess(ProcessInfo::from_pid(pid)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dirents() {
+        let mut buffer = vec![];
+        for name in [".", "..", "sshd_config"] {
+            let record_length = (DIRENT_NAME_OFFSET + name.len() + 1).next_multiple_of(8);
+            let start = buffer.len();
+            buffer.extend(1u64.to_ne_bytes());
+            buffer.extend(0i64.to_ne_bytes());
+            buffer.extend((record_length as u16).to_ne_bytes());
+            buffer.push(libc::DT_REG);
+            buffer.extend(name.as_bytes());
+            buffer.resize(start + record_length, 0);
+        }
+        let mut names = BTreeSet::new();
+        parse_dirents(&buffer, &mut names);
+        assert_eq!(names, BTreeSet::from([OsString::from("sshd_config")]));
+        assert!(has_hidden_subdirectories(5, 2));
+        assert!(!has_hidden_subdirectories(1, 2));
+    }
+
+    #[test]
+    fn test_check_listings() {
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::create_dir(directory.path().join("ssh")).unwrap();
+        std::fs::write(directory.path().join("ssh").join("sshd_config"), "").unwrap();
+        std::fs::write(directory.path().join("hosts"), "").unwrap();
+
+        let mut listings = Listings::default();
+        for entry in WalkDir::new(directory.path()).into_iter() {
+            listings.add(&entry.unwrap());
+        }
+        assert_eq!(
+            getdents(directory.path()).unwrap(),
+            BTreeSet::from([OsString::from("hosts"), OsString::from("ssh")])
+        );
+        assert!(listings.check(&Config::demo_config()).is_empty());
+    }
+}
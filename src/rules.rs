@@ -0,0 +1,87 @@
+use std::path::PathBuf;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::dispatcher::{escape_html, MessageBackend, Severity};
+
+/// A user defined rule which dispatches a message for each matching log line.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct LogRule {
+    pub name: String,
+    /// Regular expression matched against each line. Named captures are included in the message.
+    pub regex: String,
+    /// Title of the message. `$capture` is replaced by the value of the named capture.
+    pub title: String,
+    #[serde(default)]
+    pub severity: Severity,
+    /// Log file the rule is evaluated on. Defaults to the authentication logs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+}
+
+/// A [`LogRule`] with its compiled regular expression.
+pub struct CompiledRule {
+    pub rule: LogRule,
+    regex: Regex,
+}
+
+impl CompiledRule {
+    pub fn new(rule: &LogRule) -> Result<CompiledRule, regex::Error> {
+        Ok(CompiledRule {
+            rule: rule.clone(),
+            regex: Regex::new(&rule.regex)?,
+        })
+    }
+
+    /// Match each line of `contents` and return the messages of matching lines.
+    pub fn find_matches(&self, contents: &str) -> Vec<MessageBackend> {
+        contents
+            .lines()
+            .filter_map(|line| self.regex.captures(line).map(|cap| (line, cap)))
+            .map(|(line, cap)| {
+                let mut title = String::new();
+                cap.expand(&self.rule.title, &mut title);
+                let mut body = format!("<code>{}</code>\n", escape_html(line));
+                for name in self.regex.capture_names().flatten() {
+                    if let Some(value) = cap.name(name) {
+                        body.push_str(&format!("\n{name}: <b>{}</b>", escape_html(value.as_str())));
+                    }
+                }
+                body.push_str(&format!("\n\nrule: {}", self.rule.name));
+                MessageBackend::new_now(title, body).with_severity(self.rule.severity)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rule() {
+        let rule = CompiledRule::new(&LogRule {
+            name: "su".to_string(),
+            regex: r"su\[\d+\]: \(to (?P<target>\S+)\) (?P<user>\S+) on".to_string(),
+            title: "$user switched to $target".to_string(),
+            severity: Severity::High,
+            file: None,
+        })
+        .unwrap();
+        let messages = rule.find_matches(
+            "May 20 06:30:01 host su[1234]: (to root) user1 on pts/0\nMay 20 06:30:02 host cron[1]: ok",
+        );
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].title, "user1 switched to root");
+        assert_eq!(messages[0].severity, Severity::High);
+
+        let messages = rule.find_matches("host su[1]: (to root) <b>x&y</b> on pts/0");
+        assert!(messages[0]
+            .body
+            .starts_with("<code>host su[1]: (to root) &lt;b&gt;x&amp;y&lt;/b&gt; on pts/0</code>"));
+        assert!(messages[0]
+            .body
+            .contains("user: <b>&lt;b&gt;x&amp;y&lt;/b&gt;</b>"));
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, FixedOffset, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::config::Config;
+
+/// Sessions open for longer are dropped, e.g. sessions of other hosts in a shared journal whose
+/// logout was lost.
+const SESSION_TTL_DAYS: i64 = 30;
+
+#[derive(Debug, Error)]
+pub enum SessionsError {
+    #[error(transparent)]
+    IO(#[from] io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// An ssh session identified by the pid of the sshd process which handles it.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub hostname: String,
+    pub pid: u32,
+    pub username: String,
+    pub source: String,
+    pub method: String,
+    pub opened: DateTime<FixedOffset>,
+}
+
+impl Session {
+    fn key(hostname: &str, pid: u32) -> String {
+        format!("{hostname}/{pid}")
+    }
+
+    /// Returns `true` if the session belongs to this host but its sshd process is gone or it
+    /// was opened before the last boot, e.g. because the logout was not logged during a reboot.
+    pub fn is_stale(&self) -> bool {
+        let local = hostname::get()
+            .map(|hostname| hostname.to_string_lossy() == self.hostname)
+            .unwrap_or(false);
+        let before_boot = boot_time().is_some_and(|boot_time| self.opened < boot_time);
+        cfg!(target_os = "linux")
+            && local
+            && (before_boot || !Path::new(&format!("/proc/{}", self.pid)).exists())
+    }
+}
+
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} from {} using {} on {} (sshd[{}]) since {}",
+            self.username, self.source, self.method, self.hostname, self.pid, self.opened
+        )
+    }
+}
+
+/// The time of the last boot from `/proc/stat`.
+fn boot_time() -> Option<DateTime<Utc>> {
+    let stat = std::fs::read_to_string("/proc/stat").ok()?;
+    let seconds = stat
+        .lines()
+        .find_map(|line| line.strip_prefix("btime "))?
+        .trim()
+        .parse()
+        .ok()?;
+    DateTime::from_timestamp(seconds, 0)
+}
+
+/// Format `duration` like `1h 2m 3s`.
+pub fn format_duration(duration: TimeDelta) -> String {
+    let seconds = duration.num_seconds().max(0);
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    match (hours, minutes) {
+        (0, 0) => format!("{seconds}s"),
+        (0, _) => format!("{minutes}m {seconds}s"),
+        _ => format!("{hours}h {minutes}m {seconds}s"),
+    }
+}
+
+/// The open ssh sessions. The watcher loads them once and rewrites the sessions file of
+/// [`Config::sessions_path`] on every change.
+#[derive(Debug)]
+pub struct Sessions {
+    path: PathBuf,
+    sessions: BTreeMap<String, Session>,
+}
+
+impl Sessions {
+    /// Load the sessions file. Without a file there are no open sessions.
+    pub fn load(config: &Config) -> Result<Sessions, SessionsError> {
+        let path = config.sessions_path();
+        let sessions = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Sessions { path, sessions })
+    }
+
+    /// Replace the sessions file at once, so that `snitch sessions` never reads a partial file.
+    fn save(&self) -> Result<(), SessionsError> {
+        let temporary = self.path.with_extension("tmp");
+        std::fs::write(&temporary, serde_json::to_vec(&self.sessions)?)?;
+        std::fs::rename(&temporary, &self.path)?;
+        Ok(())
+    }
+
+    /// Remember `session` as open.
+    pub fn open(&mut self, session: Session) -> Result<(), SessionsError> {
+        self.sessions
+            .insert(Session::key(&session.hostname, session.pid), session);
+        self.save()
+    }
+
+    /// Forget the session handled by `pid` on `hostname` and return it if it was known.
+    pub fn close(&mut self, hostname: &str, pid: u32) -> Result<Option<Session>, SessionsError> {
+        let session = self.sessions.remove(&Session::key(hostname, pid));
+        if session.is_some() {
+            self.save()?;
+        }
+        Ok(session)
+    }
+
+    /// All sessions which were opened but not closed, oldest first.
+    pub fn list(&self) -> Vec<&Session> {
+        let mut sessions: Vec<&Session> = self.sessions.values().collect();
+        sessions.sort_by_key(|session| session.opened);
+        sessions
+    }
+
+    /// Drop the stale sessions and the sessions opened more than [`SESSION_TTL_DAYS`] before
+    /// `now`. Returns the dropped sessions.
+    pub fn prune(&mut self, now: DateTime<FixedOffset>) -> Result<Vec<Session>, SessionsError> {
+        let (pruned, open): (BTreeMap<String, Session>, BTreeMap<String, Session>) =
+            std::mem::take(&mut self.sessions)
+                .into_iter()
+                .partition(|(_, session)| {
+                    session.is_stale() || session.opened < now - TimeDelta::days(SESSION_TTL_DAYS)
+                });
+        self.sessions = open;
+        if !pruned.is_empty() {
+            self.save()?;
+        }
+        Ok(pruned.into_values().collect())
+    }
+}
+
+/// Print the open sessions.
+pub fn print_sessions(config: &Config) -> Result<(), SessionsError> {
+    let sessions = Sessions::load(config)?;
+    let sessions = sessions.list();
+    if sessions.is_empty() {
+        println!("no open sessions");
+    }
+    for session in sessions {
+        match session.is_stale() {
+            true => println!("{session} (stale)"),
+            false => println!("{session}"),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(TimeDelta::seconds(42)), "42s");
+        assert_eq!(format_duration(TimeDelta::seconds(62)), "1m 2s");
+        assert_eq!(format_duration(TimeDelta::seconds(3723)), "1h 2m 3s");
+    }
+
+    #[test]
+    fn test_prune_sessions() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut config = Config::demo_config();
+        config.snitch_root = directory.path().to_string_lossy().into_owned();
+        let now = DateTime::parse_from_rfc3339("2024-05-20T06:00:00+02:00").unwrap();
+        let session = |pid, opened: DateTime<FixedOffset>| Session {
+            hostname: "remote-host".to_string(),
+            pid,
+            username: "demo-user".to_string(),
+            source: "12.23.43.12".to_string(),
+            method: "publickey".to_string(),
+            opened,
+        };
+        let recent = session(15391, now - TimeDelta::hours(2));
+        let mut sessions = Sessions::load(&config).unwrap();
+        sessions.open(recent.clone()).unwrap();
+        sessions
+            .open(session(955719, now - TimeDelta::days(60)))
+            .unwrap();
+
+        let pruned = sessions.prune(now).unwrap();
+        assert_eq!(pruned.len(), 1);
+        assert_eq!(pruned[0].pid, 955719);
+        // the watcher and `snitch sessions` share the file
+        let sessions = Sessions::load(&config).unwrap();
+        assert_eq!(sessions.list(), vec![&recent]);
+    }
+}
@@ -0,0 +1,521 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use data_encoding::HEXUPPER;
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::time;
+
+use crate::config::Config;
+use crate::dispatcher::{MessageBackend, Severity, SnitchDispatcher};
+use crate::persist::{open_state_database, PersistError};
+
+/// Trees of the state database holding the loaded kernel modules and the boot artifacts of the
+/// baseline. They are not part of the hash database so that `--watch-system` can update them
+/// while the file watcher holds the hash database open.
+static KERNEL_MODULES_TREE: &str = "kernel_modules";
+static BOOT_TREE: &str = "boot";
+
+static PROC_MODULES: &str = "/proc/modules";
+static BOOT: &str = "/boot";
+
+/// Boot loader configurations. Kernels and initial ramdisks are found by name in `/boot`.
+static BOOT_CONFIGS: [&str; 3] = [
+    "/boot/grub/grub.cfg",
+    "/boot/grub2/grub.cfg",
+    "/etc/default/grub",
+];
+static KERNEL_PREFIXES: [&str; 1] = ["vmlinuz"];
+static INITRD_PREFIXES: [&str; 2] = ["initrd", "initramfs"];
+
+const CPIO_HEADER_LENGTH: usize = 110;
+const CPIO_TRAILER: &str = "TRAILER!!!";
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Debug, Error)]
+pub enum SystemError {
+    #[error(transparent)]
+    Sled(#[from] sled::Error),
+    #[error(transparent)]
+    Persist(#[from] PersistError),
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// A loaded kernel module as listed in `/proc/modules`.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct KernelModule {
+    pub name: String,
+    pub size: u64,
+    /// Taint flags like `O` (out of tree) or `E` (unsigned).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub taints: Option<String>,
+}
+
+pub fn parse_modules(contents: &str) -> BTreeMap<String, KernelModule> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let module = KernelModule {
+                name: fields.first()?.to_string(),
+                size: fields.get(1)?.parse().ok()?,
+                taints: fields
+                    .get(6)
+                    .map(|taints| taints.trim_matches(|c| c == '(' || c == ')').to_string()),
+            };
+            Some((module.name.clone(), module))
+        })
+        .collect()
+}
+
+/// Hash of a boot artifact and, for initial ramdisks, the hashes of the files inside.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct BootArtifact {
+    pub hash: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub contents: BTreeMap<String, String>,
+}
+
+fn sha256(data: &[u8]) -> String {
+    HEXUPPER.encode(digest(&SHA256, data).as_ref())
+}
+
+/// Returns `true` if `path` is a kernel, initial ramdisk or boot loader configuration.
+pub fn is_boot_artifact(path: &Path) -> bool {
+    if BOOT_CONFIGS.iter().any(|config| path == Path::new(config)) {
+        return true;
+    }
+    let Some(name) = path.file_name().map(|name| name.to_string_lossy()) else {
+        return false;
+    };
+    path.parent() == Some(Path::new(BOOT))
+        && KERNEL_PREFIXES
+            .iter()
+            .chain(INITRD_PREFIXES.iter())
+            .any(|prefix| name.starts_with(prefix))
+}
+
+fn boot_artifacts() -> Vec<PathBuf> {
+    let mut artifacts: Vec<PathBuf> = std::fs::read_dir(BOOT)
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .chain(BOOT_CONFIGS.iter().map(PathBuf::from))
+        .filter(|path| path.is_file() && is_boot_artifact(path))
+        .collect();
+    artifacts.sort();
+    artifacts
+}
+
+fn parse_hex(field: &[u8]) -> Option<usize> {
+    usize::from_str_radix(std::str::from_utf8(field).ok()?, 16).ok()
+}
+
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/// Hash the files of an uncompressed `newc` cpio archive at the beginning of `data` into
+/// `contents`. Returns the length of the archive.
+fn parse_cpio(data: &[u8], contents: &mut BTreeMap<String, String>) -> Option<usize> {
+    let mut offset = 0;
+    loop {
+        let header = data.get(offset..offset + CPIO_HEADER_LENGTH)?;
+        if !header.starts_with(b"07070") {
+            return None;
+        }
+        let field = |index: usize| parse_hex(&header[6 + index * 8..6 + (index + 1) * 8]);
+        let mode = field(1)?;
+        let file_size = field(6)?;
+        let name_size = field(11)?;
+        let name_start = offset + CPIO_HEADER_LENGTH;
+        let name = data.get(name_start..name_start + name_size.saturating_sub(1))?;
+        let name = String::from_utf8_lossy(name).into_owned();
+        let data_start = align4(name_start + name_size);
+        offset = align4(data_start + file_size);
+        if name == CPIO_TRAILER {
+            return Some(offset);
+        }
+        // directories carry no data
+        if mode & 0o170000 != 0o040000 {
+            contents.insert(name, sha256(data.get(data_start..data_start + file_size)?));
+        }
+    }
+}
+
+/// Hash the files of an initial ramdisk: a sequence of cpio archives, e.g. an uncompressed one
+/// with CPU microcode followed by the gzip or zstd compressed main archive.
+pub fn parse_initrd(data: &[u8]) -> BTreeMap<String, String> {
+    let mut contents = BTreeMap::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        // archives are padded with zeros
+        if data[offset] == 0 {
+            offset += 1;
+            continue;
+        }
+        let rest = &data[offset..];
+        let mut decompressed = vec![];
+        let result = if rest.starts_with(&GZIP_MAGIC) {
+            flate2::read::MultiGzDecoder::new(rest).read_to_end(&mut decompressed)
+        } else if rest.starts_with(&ZSTD_MAGIC) {
+            ruzstd::decoding::StreamingDecoder::new(rest)
+                .map_err(std::io::Error::other)
+                .and_then(|mut decoder| decoder.read_to_end(&mut decompressed))
+        } else {
+            match parse_cpio(rest, &mut contents) {
+                Some(length) => {
+                    offset += length;
+                    continue;
+                }
+                None => {
+                    debug!("unsupported initrd format at offset {offset}");
+                    break;
+                }
+            }
+        };
+        match result {
+            Ok(_) => contents.extend(parse_initrd(&decompressed)),
+            Err(e) => warn!("cannot decompress initrd: {e}"),
+        }
+        // the compressed archive is the last one
+        break;
+    }
+    contents
+}
+
+/// Read the boot artifact at `path`. The files inside an initial ramdisk are only hashed if the
+/// ramdisk differs from `known`, since decompressing it takes a while.
+fn read_artifact(
+    path: &Path,
+    known: Option<&BootArtifact>,
+) -> Result<BootArtifact, std::io::Error> {
+    let data = std::fs::read(path)?;
+    let hash = sha256(&data);
+    if let Some(known) = known.filter(|known| known.hash == hash) {
+        return Ok(known.clone());
+    }
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let contents = match INITRD_PREFIXES
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+    {
+        true => parse_initrd(&data),
+        false => BTreeMap::new(),
+    };
+    Ok(BootArtifact { hash, contents })
+}
+
+/// A change of the loaded kernel modules or the boot artifacts compared to the baseline.
+#[derive(Debug, PartialEq)]
+pub enum SystemChange {
+    ModuleLoaded(KernelModule),
+    ModuleUnloaded(KernelModule),
+    BootArtifactAdded(PathBuf),
+    BootArtifactRemoved(PathBuf),
+    BootArtifactChanged {
+        path: PathBuf,
+        /// Files inside an initial ramdisk which were added, removed or changed.
+        changed_contents: Vec<String>,
+    },
+}
+
+impl SystemChange {
+    pub fn severity(&self) -> Severity {
+        match self {
+            // out of tree or unsigned modules are typical for rootkits
+            SystemChange::ModuleLoaded(KernelModule {
+                taints: Some(taints),
+                ..
+            }) if taints.contains('O') || taints.contains('E') => Severity::Critical,
+            SystemChange::ModuleUnloaded(_) => Severity::Warning,
+            _ => Severity::High,
+        }
+    }
+
+    fn title(&self) -> &str {
+        match self {
+            SystemChange::ModuleLoaded(_) | SystemChange::ModuleUnloaded(_) => {
+                "Kernel modules changed"
+            }
+            _ => "Boot integrity",
+        }
+    }
+}
+
+impl fmt::Display for SystemChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SystemChange::ModuleLoaded(module) => {
+                write!(f, "Kernel module <b>{}</b> loaded", module.name)?;
+                if let Some(taints) = &module.taints {
+                    write!(f, " (taints: {taints})")?;
+                }
+                Ok(())
+            }
+            SystemChange::ModuleUnloaded(module) => {
+                write!(f, "Kernel module <b>{}</b> unloaded", module.name)
+            }
+            SystemChange::BootArtifactAdded(path) => {
+                write!(f, "New boot file <code>{}</code>", path.display())
+            }
+            SystemChange::BootArtifactRemoved(path) => {
+                write!(f, "Boot file <code>{}</code> removed", path.display())
+            }
+            SystemChange::BootArtifactChanged {
+                path,
+                changed_contents,
+            } => {
+                write!(f, "Boot file <code>{}</code> changed", path.display())?;
+                if !changed_contents.is_empty() {
+                    write!(f, "\nchanged files inside:")?;
+                    for file in changed_contents {
+                        write!(f, "\n<code>{file}</code>")?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl From<&SystemChange> for MessageBackend {
+    fn from(value: &SystemChange) -> Self {
+        MessageBackend::new_now(value.title().to_string(), value.to_string())
+            .with_severity(value.severity())
+    }
+}
+
+/// Names of the files which differ between two initial ramdisks.
+fn diff_contents(old: &BTreeMap<String, String>, new: &BTreeMap<String, String>) -> Vec<String> {
+    let mut changed: Vec<String> = new
+        .iter()
+        .filter(|(name, hash)| old.get(*name) != Some(*hash))
+        .map(|(name, _)| name.clone())
+        .chain(old.keys().filter(|name| !new.contains_key(*name)).cloned())
+        .collect();
+    changed.sort();
+    changed
+}
+
+fn load_tree<T: for<'de> Deserialize<'de>>(
+    tree: &sled::Tree,
+) -> Result<BTreeMap<String, T>, SystemError> {
+    tree.iter()
+        .map(|entry| {
+            let (key, value) = entry?;
+            Ok((
+                String::from_utf8_lossy(&key).into_owned(),
+                serde_json::from_slice(&value)?,
+            ))
+        })
+        .collect()
+}
+
+fn store_tree<T: Serialize>(
+    tree: &sled::Tree,
+    values: &BTreeMap<String, T>,
+) -> Result<(), SystemError> {
+    tree.clear()?;
+    for (key, value) in values {
+        tree.insert(key.as_bytes(), serde_json::to_vec(value)?)?;
+    }
+    Ok(())
+}
+
+fn current_modules() -> Result<BTreeMap<String, KernelModule>, std::io::Error> {
+    match std::fs::read_to_string(PROC_MODULES) {
+        Ok(contents) => Ok(parse_modules(&contents)),
+        // not Linux or no module support
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// The current boot artifacts. Artifacts which are unchanged compared to `known` are not parsed.
+fn current_artifacts(known: &BTreeMap<String, BootArtifact>) -> BTreeMap<String, BootArtifact> {
+    boot_artifacts()
+        .into_iter()
+        .filter_map(|path| {
+            read_artifact(&path, known.get(path.to_string_lossy().as_ref()))
+                .inspect_err(|e| warn!("cannot read {path:?}: {e}"))
+                .ok()
+                .map(|artifact| (path.to_string_lossy().into_owned(), artifact))
+        })
+        .collect()
+}
+
+/// Record the loaded kernel modules and the boot artifacts as the baseline.
+pub fn record_baseline(config: &Config) -> Result<(), SystemError> {
+    let db = open_state_database(config)?;
+    store_tree(&db.open_tree(KERNEL_MODULES_TREE)?, &current_modules()?)?;
+    store_tree(
+        &db.open_tree(BOOT_TREE)?,
+        &current_artifacts(&BTreeMap::new()),
+    )?;
+    db.flush()?;
+    Ok(())
+}
+
+/// Compare the loaded kernel modules and the boot artifacts with the baseline. If `update` is
+/// set, the baseline is replaced by the current state.
+pub fn check(config: &Config, update: bool) -> Result<Vec<SystemChange>, SystemError> {
+    let db = open_state_database(config)?;
+    let modules_tree = db.open_tree(KERNEL_MODULES_TREE)?;
+    let boot_tree = db.open_tree(BOOT_TREE)?;
+    let mut changes = vec![];
+
+    let old_modules: BTreeMap<String, KernelModule> = load_tree(&modules_tree)?;
+    let new_modules = current_modules()?;
+    // without a baseline every module would be reported
+    if !old_modules.is_empty() {
+        for (name, module) in new_modules.iter() {
+            if !old_modules.contains_key(name) {
+                changes.push(SystemChange::ModuleLoaded(module.clone()));
+            }
+        }
+        for (name, module) in old_modules.iter() {
+            if !new_modules.contains_key(name) {
+                changes.push(SystemChange::ModuleUnloaded(module.clone()));
+            }
+        }
+    }
+
+    let old_artifacts: BTreeMap<String, BootArtifact> = load_tree(&boot_tree)?;
+    let new_artifacts = current_artifacts(&old_artifacts);
+    if !old_artifacts.is_empty() {
+        for (path, artifact) in new_artifacts.iter() {
+            match old_artifacts.get(path) {
+                None => changes.push(SystemChange::BootArtifactAdded(PathBuf::from(path))),
+                Some(old) if old.hash != artifact.hash => {
+                    changes.push(SystemChange::BootArtifactChanged {
+                        path: PathBuf::from(path),
+                        changed_contents: diff_contents(&old.contents, &artifact.contents),
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+        for path in old_artifacts.keys() {
+            if !new_artifacts.contains_key(path) {
+                changes.push(SystemChange::BootArtifactRemoved(PathBuf::from(path)));
+            }
+        }
+    }
+
+    if update {
+        store_tree(&modules_tree, &new_modules)?;
+        store_tree(&boot_tree, &new_artifacts)?;
+        db.flush()?;
+    }
+    Ok(changes)
+}
+
+/// Check the kernel modules and boot artifacts every `system_interval` seconds.
+pub async fn watch_system(config: &Config, dispatcher: &SnitchDispatcher) {
+    info!("start watching kernel modules and boot files");
+    let mut interval = time::interval(Duration::from_secs(config.system_interval));
+    loop {
+        interval.tick().await;
+        let changes = match check(config, true) {
+            Ok(changes) => changes,
+            Err(e) => {
+                error!("failed checking kernel modules and boot files: {e}");
+                continue;
+            }
+        };
+        for change in changes.iter() {
+            warn!("{change}");
+            let _ = dispatcher
+                .dispatch(change.into())
+                .await
+                .inspect_err(|e| error!("failed to dispatch message: {:?}", e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn cpio(entries: &[(&str, u32, &[u8])]) -> Vec<u8> {
+        let mut archive = vec![];
+        let mut append = |name: &str, mode: u32, data: &[u8]| {
+            archive.extend(format!(
+                "070701{:08X}{mode:08X}{:08X}{:08X}{:08X}{:08X}{:08X}{:08X}{:08X}{:08X}{:08X}{:08X}{:08X}",
+                0, 0, 0, 1, 0, data.len(), 0, 0, 0, 0, name.len() + 1, 0
+            ).as_bytes());
+            archive.extend(name.as_bytes());
+            archive.push(0);
+            archive.resize(align4(archive.len()), 0);
+            archive.extend(data);
+            archive.resize(align4(archive.len()), 0);
+        };
+        for (name, mode, data) in entries {
+            append(name, *mode, data);
+        }
+        append(CPIO_TRAILER, 0, b"");
+        archive
+    }
+
+    #[test]
+    fn test_parse_initrd() {
+        let mut initrd = cpio(&[("kernel/x86/microcode/GenuineIntel.bin", 0o100644, b"ucode")]);
+        initrd.resize(512, 0);
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder
+            .write_all(&cpio(&[
+                ("usr", 0o040755, b""),
+                ("usr/bin/init", 0o100755, b"#!/bin/sh\n"),
+            ]))
+            .unwrap();
+        initrd.extend(encoder.finish().unwrap());
+
+        let contents = parse_initrd(&initrd);
+        assert_eq!(
+            contents.keys().collect::<Vec<&String>>(),
+            vec!["kernel/x86/microcode/GenuineIntel.bin", "usr/bin/init"]
+        );
+        assert_eq!(contents["usr/bin/init"], sha256(b"#!/bin/sh\n"));
+
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("initrd.img-6.1.0-18-amd64");
+        std::fs::write(&path, &initrd).unwrap();
+        let artifact = read_artifact(&path, None).unwrap();
+        assert_eq!(artifact.contents, contents);
+        // an unchanged ramdisk is not parsed again
+        let known = BootArtifact {
+            hash: artifact.hash.clone(),
+            contents: BTreeMap::new(),
+        };
+        assert_eq!(read_artifact(&path, Some(&known)).unwrap(), known);
+    }
+
+    #[test]
+    fn test_parse_modules() {
+        let modules = parse_modules(
+            "ext4 1060864 1 - Live 0x0000000000000000\n\
+            diamorphine 16384 0 - Live 0x0000000000000000 (OE)\n",
+        );
+        assert_eq!(modules.len(), 2);
+        assert_eq!(modules["ext4"].taints, None);
+        let loaded = SystemChange::ModuleLoaded(modules["diamorphine"].clone());
+        assert_eq!(loaded.severity(), Severity::Critical);
+        assert!(is_boot_artifact(Path::new(
+            "/boot/initrd.img-6.1.0-18-amd64"
+        )));
+        assert!(!is_boot_artifact(Path::new("/boot/config-6.1.0-18-amd64")));
+    }
+}
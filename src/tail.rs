@@ -0,0 +1,241 @@
+use std::fs::Metadata;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, SeekFrom};
+
+/// Identifies the file behind a path. Changes if a log file was rotated.
+#[cfg(unix)]
+fn file_id(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.ino()
+}
+
+/// Without inodes rotation can only be detected by a shrinking file.
+#[cfg(not(unix))]
+fn file_id(_metadata: &Metadata) -> u64 {
+    0
+}
+
+/// The path logrotate moves `path` to, e.g. `/var/log/auth.log.1`.
+pub fn rotated_path(path: &Path) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(".1");
+    PathBuf::from(rotated)
+}
+
+/// Follows a log file like `tail -F`. Rotated files are drained and the new file is reopened.
+/// Truncated files are read from the start.
+pub struct LogTail {
+    path: PathBuf,
+    file: File,
+    id: u64,
+    position: u64,
+    /// Incomplete last line which is completed by the next read.
+    partial: Vec<u8>,
+    /// Started reading in the middle of a line which needs to be dropped.
+    skip_line: bool,
+}
+
+impl LogTail {
+    /// Open `path` and start reading at its end.
+    pub async fn open_at_end(path: &Path) -> io::Result<LogTail> {
+        let file = File::open(path).await?;
+        let metadata = file.metadata().await?;
+        Ok(LogTail {
+            path: path.to_path_buf(),
+            file,
+            id: file_id(&metadata),
+            position: metadata.len(),
+            partial: vec![],
+            skip_line: false,
+        })
+    }
+
+    /// Open `path` and resume reading at `position` if it still is the file identified by `id`.
+    /// If the file was rotated in the meantime, the rest of the rotated file is read before
+    /// `path`. At most `limit` bytes are replayed.
+    pub async fn open_at(path: &Path, id: u64, position: u64, limit: u64) -> io::Result<LogTail> {
+        let mut tail = LogTail::open_at_end(path).await?;
+        if tail.id != id && tail.position < limit {
+            if let Some(mut rotated) = LogTail::open_rotated(path, id).await? {
+                if position <= rotated.position {
+                    rotated.replay_from(position, limit - tail.position).await?;
+                    // read_lines continues with the new file once the rotated one is drained
+                    rotated.path = path.to_path_buf();
+                    return Ok(rotated);
+                }
+            }
+        }
+        let resume_at = match tail.id == id && position <= tail.position {
+            true => position,
+            false => 0,
+        };
+        tail.replay_from(resume_at, limit).await?;
+        Ok(tail)
+    }
+
+    /// Open the file `path` was rotated to if it is the file identified by `id`.
+    async fn open_rotated(path: &Path, id: u64) -> io::Result<Option<LogTail>> {
+        match LogTail::open_at_end(&rotated_path(path)).await {
+            Ok(tail) => Ok((tail.id == id).then_some(tail)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Continue reading at `position`, skipping all but the last `limit` bytes.
+    async fn replay_from(&mut self, position: u64, limit: u64) -> io::Result<()> {
+        let length = self.position;
+        if length - position > limit {
+            warn!(
+                "skipping {} bytes of {:?}. Only replaying the last {limit} bytes.",
+                length - position - limit,
+                self.path
+            );
+            self.position = length - limit;
+            // only drop the first line if replaying starts in the middle of it
+            self.skip_line = self.position > 0 && !self.follows_newline().await?;
+        } else {
+            self.position = position;
+        }
+        Ok(())
+    }
+
+    /// The file id and the position up to which complete lines have been read.
+    pub fn offset(&self) -> (u64, u64) {
+        (self.id, self.position - self.partial.len() as u64)
+    }
+
+    /// Read all complete lines that were appended since the last read.
+    pub async fn read_lines(&mut self) -> io::Result<String> {
+        let mut contents = std::mem::take(&mut self.partial);
+        self.read_to_end(&mut contents).await?;
+
+        match tokio::fs::metadata(&self.path).await {
+            Ok(metadata) if file_id(&metadata) != self.id => {
+                info!("{:?} was rotated. Reopening.", self.path);
+                // the rotated file may end with an incomplete line
+                if !contents.is_empty() && !contents.ends_with(b"\n") {
+                    contents.push(b'\n');
+                }
+                self.file = File::open(&self.path).await?;
+                self.id = file_id(&metadata);
+                self.position = 0;
+                self.read_to_end(&mut contents).await?;
+            }
+            Ok(metadata) if metadata.len() < self.position => {
+                info!("{:?} was truncated. Reading from start.", self.path);
+                self.position = 0;
+                self.read_to_end(&mut contents).await?;
+            }
+            Ok(_) => {}
+            // rotated but not yet recreated
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+
+        if self.skip_line {
+            match contents.iter().position(|byte| *byte == b'\n') {
+                Some(end) => {
+                    contents.drain(..=end);
+                    self.skip_line = false;
+                }
+                None => contents.clear(),
+            }
+        }
+
+        if let Some(end) = contents.iter().rposition(|byte| *byte == b'\n') {
+            self.partial = contents.split_off(end + 1);
+        } else {
+            self.partial = std::mem::take(&mut contents);
+        }
+        Ok(String::from_utf8_lossy(&contents).into_owned())
+    }
+
+    /// Returns `true` if the byte before the current position is a line break.
+    async fn follows_newline(&mut self) -> io::Result<bool> {
+        let mut byte = [0u8];
+        self.file.seek(SeekFrom::Start(self.position - 1)).await?;
+        self.file.read_exact(&mut byte).await?;
+        Ok(byte[0] == b'\n')
+    }
+
+    async fn read_to_end(&mut self, contents: &mut Vec<u8>) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(self.position)).await?;
+        self.position += self.file.read_to_end(contents).await? as u64;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+
+    fn append(path: &Path, line: &str) {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        file.write_all(line.as_bytes()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rotation_and_truncation() {
+        let directory = tempfile::tempdir().unwrap();
+        let log = directory.path().join("auth.log");
+        append(&log, "old\n");
+
+        let mut tail = LogTail::open_at_end(&log).await.unwrap();
+        append(&log, "first\nincompl");
+        assert_eq!(tail.read_lines().await.unwrap(), "first\n");
+
+        // logrotate moves the file aside, the writer still appends to it
+        fs::rename(&log, directory.path().join("auth.log.1")).unwrap();
+        append(&directory.path().join("auth.log.1"), "ete\nrotated tail\n");
+        append(&log, "new file\n");
+        assert_eq!(
+            tail.read_lines().await.unwrap(),
+            "incomplete\nrotated tail\nnew file\n"
+        );
+
+        // copytruncate
+        fs::write(&log, "").unwrap();
+        append(&log, "short\n");
+        assert_eq!(tail.read_lines().await.unwrap(), "short\n");
+    }
+
+    #[tokio::test]
+    async fn test_resume() {
+        let directory = tempfile::tempdir().unwrap();
+        let log = directory.path().join("auth.log");
+        append(&log, "seen\n");
+        let (id, position) = LogTail::open_at_end(&log).await.unwrap().offset();
+
+        append(&log, "missed\nmissed too\n");
+        let mut tail = LogTail::open_at(&log, id, position, 1000).await.unwrap();
+        assert_eq!(tail.read_lines().await.unwrap(), "missed\nmissed too\n");
+
+        let mut tail = LogTail::open_at(&log, id, position, 12).await.unwrap();
+        assert_eq!(tail.read_lines().await.unwrap(), "missed too\n");
+
+        // the limit ends exactly at the start of a line
+        let mut tail = LogTail::open_at(&log, id, position, 11).await.unwrap();
+        assert_eq!(tail.read_lines().await.unwrap(), "missed too\n");
+
+        // rotated while not running
+        fs::rename(&log, rotated_path(&log)).unwrap();
+        append(&log, "new file\n");
+        let mut tail = LogTail::open_at(&log, id, position, 1000).await.unwrap();
+        assert_eq!(
+            tail.read_lines().await.unwrap(),
+            "missed\nmissed too\nnew file\n"
+        );
+        let mut tail = LogTail::open_at(&log, id, position, 20).await.unwrap();
+        assert_eq!(tail.read_lines().await.unwrap(), "missed too\nnew file\n");
+    }
+}